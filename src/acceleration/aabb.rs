@@ -27,13 +27,22 @@ impl AABB {
         Self::new(min, max)
     }
 
+    pub fn is_finite(&self) -> bool {
+        (0..3).all(|a| self.min[a].is_finite() && self.max[a].is_finite())
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let size = self.max - self.min;
+        2.0 * (size.x() * size.y() + size.y() * size.z() + size.z() * size.x())
+    }
+
     pub fn volume(&self) -> f64 {
         (self.max.x() - self.min.x())
             * (self.max.y() - self.min.y())
             * (self.max.z() - self.min.z())
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
             let inv_d = 1.0 / r.direction[a];
             let mut t = (
@@ -43,8 +52,9 @@ impl AABB {
             if inv_d < 0.0 {
                 t = (t.1, t.0)
             }
-            let t_min = t.0.max(t_min);
-            let t_max = t.1.min(t_max);
+            // Narrow the interval axis by axis, the ray is in the box where all three overlap
+            t_min = t.0.max(t_min);
+            t_max = t.1.min(t_max);
             if t_max <= t_min {
                 return false;
             }
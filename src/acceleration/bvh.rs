@@ -1,14 +1,80 @@
-use std::cmp::Ordering;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-use rand::{thread_rng, Rng};
-
-use crate::acceleration::hit_list;
 use crate::*;
 
+// Split candidates tried along an axis
+const BINS: usize = 12;
+// Leaves hold at most this many items, unless their centers coincide
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of visiting a node, relative to hitting one item
+const TRAVERSAL_COST: f64 = 0.125;
+
+/// Bounding volume hierarchy over objects, usually the top level over instances of shared geometry
+///
+/// Objects without a finite bounding box, like a sky, can't be placed in the hierarchy and are
+/// tested by every ray instead.
 pub struct BVH {
-    left: Arc<dyn Hittable + Send + Sync>,
-    right: Arc<dyn Hittable + Send + Sync>,
-    bound_box: AABB,
+    // None if no object is bounded
+    tree: Option<BvhTree>,
+    objects: Vec<Arc<dyn Hittable + Send + Sync>>,
+    unbounded: Vec<Arc<dyn Hittable + Send + Sync>>,
+}
+
+/// Hierarchy over items known only by index and bounding box, built with the binned surface
+/// area heuristic
+///
+/// Shared geometry such as meshes keeps one of these as its bottom level, so instancing it
+/// costs no rebuild. Nodes live in one array. An interior node is followed by its left child,
+/// and stores where its right child is.
+pub struct BvhTree {
+    nodes: Vec<Node>,
+    // Item indices in leaf order, so every leaf is a range of this
+    items: Vec<u32>,
+    stats: Arc<BvhStats>,
+}
+
+struct Node {
+    bbox: AABB,
+    // First item for leaves, right child for interior nodes
+    offset: u32,
+    // Number of items, 0 for interior nodes
+    count: u32,
+    // Axis the node was split on, to visit the nearer child first
+    axis: u8,
+}
+
+/// Shape and build time of a BVH
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    pub items: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    pub build_time: Duration,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BVH over {} items: {} nodes, {} leaves of at most {} items, depth {}, built in {:.1} ms",
+            self.items,
+            self.nodes,
+            self.leaves,
+            self.max_leaf_size,
+            self.max_depth,
+            self.build_time.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+// An item waiting to be put in a leaf
+struct Primitive {
+    index: usize,
+    bbox: AABB,
+    centroid: Point3,
 }
 
 impl BVH {
@@ -16,81 +82,454 @@ impl BVH {
         Self::node(world.objects, time)
     }
 
-    pub fn node(mut objects: Vec<Arc<dyn Hittable + Send + Sync>>, time: (f64, f64)) -> Self {
-        let objects_box = hit_list::bounding_box(&objects, time).unwrap();
-        let axis =
-            if objects_box.max[0] - objects_box.min[0] > objects_box.max[1] - objects_box.min[1] {
-                0
+    pub fn node(objects: Vec<Arc<dyn Hittable + Send + Sync>>, time: (f64, f64)) -> Self {
+        let (bounded, unbounded) = partition_bounded(objects, time);
+        let (objects, boxes): (Vec<_>, Vec<_>) = bounded.into_iter().unzip();
+        Self {
+            tree: if boxes.is_empty() {
+                None
             } else {
-                1
-            };
-        let axis = if objects_box.max[2] - objects_box.min[2]
-            > objects_box.max[axis] - objects_box.min[axis]
-        {
-            2
-        } else {
-            axis
+                Some(BvhTree::new(&boxes))
+            },
+            objects,
+            unbounded,
+        }
+    }
+
+    pub fn stats(&self) -> Option<&Arc<BvhStats>> {
+        self.tree.as_ref().map(BvhTree::stats)
+    }
+}
+
+impl BvhTree {
+    /// Boxes have to be finite, as the center of an infinite one is NaN
+    pub fn new(boxes: &[AABB]) -> Self {
+        assert!(!boxes.is_empty(), "BVH needs at least one item");
+        assert!(
+            boxes.iter().all(AABB::is_finite),
+            "BVH items must be finite"
+        );
+        assert!(boxes.len() <= u32::MAX as usize);
+        let build_start = Instant::now();
+
+        let mut primitives: Vec<Primitive> = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bbox)| Primitive {
+                index,
+                bbox: bbox.clone(),
+                centroid: (bbox.min + bbox.max) * 0.5,
+            })
+            .collect();
+
+        let mut tree = Self {
+            nodes: Vec::with_capacity(2 * boxes.len()),
+            items: Vec::with_capacity(boxes.len()),
+            stats: Arc::default(),
         };
-        //let axis = thread_rng().gen_range(0, 3);
-
-        objects.sort_by(|a, b| {
-            let a_box = a
-                .bounding_box(time.0, time.1)
-                .expect("Object has bounding box");
-            let b_box = b
-                .bounding_box(time.0, time.1)
-                .expect("Object has bounding box");
-
-            if a_box.min[axis] == b_box.min[axis] {
-                Ordering::Equal
-            } else if a_box.min[axis] < b_box.min[axis] {
-                Ordering::Less
+        let mut stats = BvhStats {
+            items: boxes.len(),
+            ..BvhStats::default()
+        };
+        tree.build(&mut primitives, 1, &mut stats);
+        stats.nodes = tree.nodes.len();
+        stats.build_time = build_start.elapsed();
+        tree.stats = Arc::new(stats);
+        tree
+    }
+
+    pub fn bounding_box(&self) -> &AABB {
+        &self.nodes[0].bbox
+    }
+
+    pub fn stats(&self) -> &Arc<BvhStats> {
+        &self.stats
+    }
+
+    /// Closest hit along the ray, where hit_item hits an item given the closest distance so far
+    pub fn hit<'a, F>(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        mut hit_item: F,
+    ) -> Option<HitRecord<'a>>
+    where
+        F: FnMut(usize, f64) -> Option<HitRecord<'a>>,
+    {
+        let mut closest: Option<HitRecord> = None;
+        let mut t_max = t_max;
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox.hit(r, t_min, t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                let leaf = node.offset as usize..(node.offset + node.count) as usize;
+                for item in &self.items[leaf] {
+                    if let Some(hit) = hit_item(*item as usize, t_max) {
+                        t_max = hit.distance;
+                        closest = Some(hit);
+                    }
+                }
+            } else if r.direction[node.axis as usize] < 0.0 {
+                // Right child is nearer, so it goes on top of the stack
+                stack.push(index + 1);
+                stack.push(node.offset as usize);
+            } else {
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
+            }
+        }
+        closest
+    }
+
+    /// Whether any item is hit, where occluded_item tests a single item
+    pub fn occluded<F>(&self, r: &Ray, t_min: f64, t_max: f64, mut occluded_item: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bbox.hit(r, t_min, t_max) {
+                continue;
+            }
+            if node.count > 0 {
+                let leaf = node.offset as usize..(node.offset + node.count) as usize;
+                if self.items[leaf]
+                    .iter()
+                    .any(|item| occluded_item(*item as usize))
+                {
+                    return true;
+                }
             } else {
-                Ordering::Greater
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
             }
+        }
+        false
+    }
+
+    // Adds the subtree over primitives, returning the index of its root
+    fn build(&mut self, primitives: &mut [Primitive], depth: usize, stats: &mut BvhStats) -> usize {
+        stats.max_depth = stats.max_depth.max(depth);
+        let bbox = primitives
+            .iter()
+            .skip(1)
+            .fold(primitives[0].bbox.clone(), |bbox, primitive| {
+                bbox.surrounding_box(&primitive.bbox)
+            });
+
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            bbox,
+            offset: 0,
+            count: 0,
+            axis: 0,
         });
 
-        let (left, right) = if objects.len() == 1 {
-            (objects[0].clone(), objects[0].clone())
-        } else if objects.len() == 2 {
-            (objects[0].clone(), objects[1].clone())
-        } else {
-            let right_objects = objects.split_off(objects.len() / 2);
-            let left: Arc<dyn Hittable + Send + Sync> = Arc::new(Self::node(objects, time));
-            let right: Arc<dyn Hittable + Send + Sync> = Arc::new(Self::node(right_objects, time));
-            (left, right)
+        match self.split(primitives, &self.nodes[node].bbox) {
+            Some((axis, mid)) => {
+                let (left, right) = primitives.split_at_mut(mid);
+                self.build(left, depth + 1, stats);
+                let right = self.build(right, depth + 1, stats);
+                self.nodes[node].offset = right as u32;
+                self.nodes[node].axis = axis as u8;
+            }
+            None => {
+                self.nodes[node].offset = self.items.len() as u32;
+                self.nodes[node].count = primitives.len() as u32;
+                self.items
+                    .extend(primitives.iter().map(|primitive| primitive.index as u32));
+                stats.leaves += 1;
+                stats.max_leaf_size = stats.max_leaf_size.max(primitives.len());
+            }
+        }
+        node
+    }
+
+    // Partitions primitives at the cheapest split and returns its axis and position,
+    // or None if they are better off in a leaf
+    fn split(&self, primitives: &mut [Primitive], bbox: &AABB) -> Option<(usize, usize)> {
+        if primitives.len() == 1 {
+            return None;
+        }
+
+        let centroid_box = primitives.iter().skip(1).fold(
+            AABB::new(primitives[0].centroid, primitives[0].centroid),
+            |bbox, primitive| {
+                bbox.surrounding_box(&AABB::new(primitive.centroid, primitive.centroid))
+            },
+        );
+        let extent = centroid_box.max - centroid_box.min;
+        let axis = (0..3)
+            .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+            .unwrap();
+        if extent[axis] <= 0.0 {
+            // Nothing to tell the primitives apart by
+            return None;
+        }
+
+        let bin = |primitive: &Primitive| {
+            let offset = (primitive.centroid[axis] - centroid_box.min[axis]) / extent[axis];
+            ((offset * BINS as f64) as usize).min(BINS - 1)
         };
+        let mut bins: Vec<(usize, Option<AABB>)> = vec![(0, None); BINS];
+        for primitive in primitives.iter() {
+            let (count, bin_box) = &mut bins[bin(primitive)];
+            *count += 1;
+            *bin_box = Some(surround(bin_box.take(), &primitive.bbox));
+        }
 
-        let left_box = left
-            .bounding_box(time.0, time.1)
-            .expect("Object has bounding box");
-        let right_box = right
-            .bounding_box(time.0, time.1)
-            .expect("Object has bounding box");
+        // Cost of splitting after each bin, summing the sides from both ends
+        let mut costs = [0.0; BINS - 1];
+        let mut count = 0;
+        let mut area_box: Option<AABB> = None;
+        for (split, (bin_count, bin_box)) in bins.iter().enumerate().take(BINS - 1) {
+            count += bin_count;
+            if let Some(bin_box) = bin_box {
+                area_box = Some(surround(area_box, bin_box));
+            }
+            costs[split] = count as f64 * area_box.as_ref().map_or(0.0, AABB::surface_area);
+        }
+        let mut count = 0;
+        let mut area_box: Option<AABB> = None;
+        for (split, (bin_count, bin_box)) in bins.iter().enumerate().skip(1).rev() {
+            count += bin_count;
+            if let Some(bin_box) = bin_box {
+                area_box = Some(surround(area_box, bin_box));
+            }
+            costs[split - 1] += count as f64 * area_box.as_ref().map_or(0.0, AABB::surface_area);
+        }
 
-        let bound_box = left_box.surrounding_box(&right_box);
-        Self {
-            left,
-            right,
-            bound_box,
+        let (split, cost) = costs
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        let split_cost = TRAVERSAL_COST + cost / bbox.surface_area().max(f64::MIN_POSITIVE);
+        if primitives.len() <= MAX_LEAF_SIZE && split_cost >= primitives.len() as f64 {
+            return None;
+        }
+
+        // Move the primitives left of the split to the front
+        let mut mid = 0;
+        for i in 0..primitives.len() {
+            if bin(&primitives[i]) <= split {
+                primitives.swap(i, mid);
+                mid += 1;
+            }
         }
+        if mid == 0 || mid == primitives.len() {
+            return None;
+        }
+        Some((axis, mid))
+    }
+}
+
+fn surround(bbox: Option<AABB>, other: &AABB) -> AABB {
+    match bbox {
+        Some(bbox) => bbox.surrounding_box(other),
+        None => other.clone(),
     }
 }
 
 impl Hittable for BVH {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.bound_box.hit(r, t_min, t_max) {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        let mut closest = self.tree.as_ref().and_then(|tree| {
+            tree.hit(r, t_min, t_max, |object, t_max| {
+                self.objects[object].hit(r, t_min, t_max, rng)
+            })
+        });
+        for object in &self.unbounded {
+            let t_max = closest.as_ref().map_or(t_max, |hit| hit.distance);
+            if let Some(hit) = object.hit(r, t_min, t_max, rng) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        let bounded = self.tree.as_ref().is_some_and(|tree| {
+            tree.occluded(r, t_min, t_max, |object| {
+                self.objects[object].occluded(r, t_min, t_max, rng)
+            })
+        });
+        bounded
+            || self
+                .unbounded
+                .iter()
+                .any(|object| object.occluded(r, t_min, t_max, rng))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        if !self.unbounded.is_empty() {
             return None;
         }
+        self.tree.as_ref().map(|tree| tree.bounding_box().clone())
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.objects
+            .iter()
+            .chain(&self.unbounded)
+            .flat_map(|object| object.emitters())
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        let mut stats: Vec<Arc<BvhStats>> = self.stats().cloned().into_iter().collect();
+        stats.extend(
+            self.objects
+                .iter()
+                .chain(&self.unbounded)
+                .flat_map(|object| object.bvh_stats()),
+        );
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
 
-        if let Some(hit) = self.left.hit(r, t_min, t_max) {
-            self.right.hit(r, t_min, hit.distance).or(Some(hit))
-        } else {
-            self.right.hit(r, t_min, t_max)
+    use super::*;
+
+    fn random_point(rng: &mut SampleRng, size: f64) -> Point3 {
+        Vec3(
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+            rng.gen_range(-size, size),
+        )
+    }
+
+    // Scattered objects of all sizes, and clumps that overlap too much to be worth splitting
+    fn random_objects(rng: &mut SampleRng) -> HitList {
+        let mut objects = HitList::new();
+        for _ in 0..20 {
+            let center = random_point(rng, 10.0);
+            for _ in 0..3 {
+                objects.add(Sphere::new(
+                    center + random_point(rng, 0.01),
+                    rng.gen_range(0.3, 0.5),
+                    Lambertian::from_rgb(0.5, 0.5, 0.5),
+                ));
+            }
+        }
+        for _ in 0..150 {
+            objects.add(Sphere::new(
+                random_point(rng, 10.0),
+                rng.gen_range(0.05, 1.5),
+                Lambertian::from_rgb(0.5, 0.5, 0.5),
+            ));
+            let a = random_point(rng, 10.0);
+            objects.add(Triangle::new(
+                a,
+                a + random_point(rng, 2.0),
+                a + random_point(rng, 2.0),
+                Lambertian::from_rgb(0.5, 0.5, 0.5),
+            ));
         }
+        objects
     }
 
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        Some(self.bound_box.clone())
+    // Every object has a material of its own, so it tells which one was hit
+    fn hit_object(hit: &HitRecord) -> (*const u8, f64) {
+        (
+            hit.material as *const dyn Material as *const u8,
+            hit.distance,
+        )
+    }
+
+    #[test]
+    fn finds_the_same_hits_as_testing_every_object() {
+        let mut rng = SampleRng::seed_from_u64(7);
+        let list = random_objects(&mut rng);
+        let bvh = BVH::from_hit_list(list.clone(), (0.0, 1.0));
+        let stats = bvh.stats().unwrap();
+        assert!(stats.max_leaf_size > 1 && stats.max_depth > 3, "{}", stats);
+
+        let mut hits = 0;
+        for i in 0..5000 {
+            let origin = random_point(&mut rng, 15.0);
+            // Half of the rays head into the objects, the rest anywhere
+            let direction = if i % 2 == 0 {
+                random_point(&mut rng, 10.0) - origin
+            } else {
+                random_point(&mut rng, 1.0)
+            };
+            let r = Ray::new(origin, direction, 0.0);
+            let t_max = if i % 3 == 0 {
+                rng.gen_range(0.0, 2.0)
+            } else {
+                f64::INFINITY
+            };
+
+            let expected = list
+                .hit(&r, 0.001, t_max, &mut rng)
+                .map(|hit| hit_object(&hit));
+            let found = bvh
+                .hit(&r, 0.001, t_max, &mut rng)
+                .map(|hit| hit_object(&hit));
+            assert_eq!(
+                found, expected,
+                "ray {} from {:?} along {:?}",
+                i, origin, direction
+            );
+            assert_eq!(bvh.occluded(&r, 0.001, t_max, &mut rng), expected.is_some());
+            assert_eq!(
+                list.occluded(&r, 0.001, t_max, &mut rng),
+                expected.is_some()
+            );
+            hits += expected.is_some() as usize;
+        }
+        // Enough of both to mean something
+        assert!(hits > 1000 && hits < 4000, "{} hits", hits);
+    }
+
+    #[test]
+    fn tests_objects_reaching_infinity_on_every_ray() {
+        let mut objects = HitList::new();
+        objects.add(Sphere::new(
+            Vec3(0.0, 0.0, -5.0),
+            1.0,
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        ));
+        let mut infinite = HitList::new();
+        infinite.add(Sphere::new(
+            Vec3(3.0, 0.0, 0.0),
+            f64::INFINITY,
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        ));
+        infinite.add(Sphere::new(
+            Vec3(0.0, 0.0, 5.0),
+            1.0,
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        ));
+        // Straight, and inside a list whose box it stretches to infinity
+        objects.add(infinite.objects[0].clone());
+        objects.add(Arc::new(infinite));
+        objects.add(Sphere::new(
+            Vec3(f64::MAX, 0.0, 0.0),
+            f64::MAX,
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        ));
+
+        let bvh = BVH::from_hit_list(objects, (0.0, 1.0));
+        assert_eq!(bvh.stats().unwrap().items, 1);
+        assert!(bvh.bounding_box(0.0, 1.0).is_none());
+        let mut rng = SampleRng::seed_from_u64(0);
+        for &(z, distance) in [(-1.0, 4.0), (1.0, 4.0)].iter() {
+            let r = Ray::new(Vec3::zero(), Vec3(0.0, 0.0, z), 0.0);
+            let hit = bvh.hit(&r, 0.001, f64::INFINITY, &mut rng).unwrap();
+            assert!((hit.distance - distance).abs() < 1e-9);
+        }
     }
 }
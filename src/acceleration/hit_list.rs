@@ -2,6 +2,10 @@ use std::sync::Arc;
 
 use crate::*;
 
+type ThreadHittable = dyn Hittable + Send + Sync;
+type BoundedObject = (Arc<ThreadHittable>, AABB);
+
+#[derive(Clone, Default)]
 pub struct HitList {
     pub objects: Vec<Arc<dyn Hittable + Send + Sync>>,
 }
@@ -23,12 +27,12 @@ impl HitList {
 }
 
 impl Hittable for HitList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
         let mut closest_so_far = t_max;
         let mut final_hit_record: Option<HitRecord> = None;
 
         for object in self.objects.iter() {
-            if let Some(hit_record) = object.hit(r, t_min, closest_so_far) {
+            if let Some(hit_record) = object.hit(r, t_min, closest_so_far, rng) {
                 closest_so_far = hit_record.distance;
                 final_hit_record = Some(hit_record);
             }
@@ -40,12 +44,51 @@ impl Hittable for HitList {
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         bounding_box(&self.objects, (t0, t1))
     }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        self.objects
+            .iter()
+            .any(|object| object.occluded(r, t_min, t_max, rng))
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(r))
+            .sum()
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        // Picks an object with the first coordinate, then reuses what is left of it
+        let scaled = u.0 * self.objects.len() as f64;
+        let index = (scaled as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin, time, (scaled - index as f64, u.1))
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.emitters())
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.bvh_stats())
+            .collect()
+    }
 }
 
-pub fn bounding_box(
-    objects: &Vec<Arc<dyn Hittable + Send + Sync>>,
-    time: (f64, f64),
-) -> Option<AABB> {
+/// Box around all objects, or None if there are none or any of them is unbounded
+///
+/// A box around only the bounded objects would let an enclosing BVH skip the rest,
+/// it keeps the whole list among its unbounded objects instead.
+pub fn bounding_box(objects: &[Arc<dyn Hittable + Send + Sync>], time: (f64, f64)) -> Option<AABB> {
     if objects.is_empty() {
         return None;
     }
@@ -65,3 +108,21 @@ pub fn bounding_box(
     }
     final_box
 }
+
+/// Pairs the objects that have a bounding box with it, and sets apart those that don't
+///
+/// Boxes reaching infinity can't be split, so their objects are set apart too.
+pub fn partition_bounded(
+    objects: Vec<Arc<ThreadHittable>>,
+    time: (f64, f64),
+) -> (Vec<BoundedObject>, Vec<Arc<ThreadHittable>>) {
+    let mut bounded = vec![];
+    let mut unbounded = vec![];
+    for object in objects {
+        match object.bounding_box(time.0, time.1) {
+            Some(bbox) if bbox.is_finite() => bounded.push((object, bbox)),
+            _ => unbounded.push(object),
+        }
+    }
+    (bounded, unbounded)
+}
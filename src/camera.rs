@@ -1,5 +1,3 @@
-use rand::thread_rng;
-
 use crate::*;
 
 pub struct Camera {
@@ -9,7 +7,6 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
     time_start: f64,
     time_end: f64,
@@ -42,7 +39,6 @@ impl Camera {
                 - focus_dist * w,
             horizontal: 2.0 * half_width * focus_dist * u,
             vertical: 2.0 * half_height * focus_dist * v,
-            w,
             u,
             v,
             lens_radius,
@@ -51,14 +47,15 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    /// Ray through (s, t) on the screen, with the lens and time dimensions from sampler
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * Vec3::sample_in_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x() + self.v * rd.y();
         let new_origin = self.origin + offset;
         Ray::new(
             new_origin,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - new_origin,
-            thread_rng().gen_range(self.time_start, self.time_end),
+            self.time_start + sampler.get_1d() * (self.time_end - self.time_start),
         )
     }
 }
@@ -0,0 +1,277 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+// Start of every checkpoint file, with the version of the format
+const MAGIC: &[u8] = b"PTCHECK3";
+
+/// Everything needed to continue a progressive render where it stopped
+///
+/// Written after every pass and when a render is interrupted. The float framebuffer is kept,
+/// not the 8 bit image.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // Names the scene, so checkpoints of different scenes aren't mixed up
+    pub scene: String,
+    pub config: Config,
+    // Seeds of the renders in the framebuffer, so none is merged in twice
+    pub seeds: Vec<u64>,
+    // Samples per pixel taken by the passes that are done
+    pub samples_done: u32,
+    // Tiles of the next pass that are done, if the render was interrupted in it
+    pub tiles_done: Vec<Tile>,
+    pub framebuffer: Framebuffer,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        let bytes = fs::read(path).map_err(|source| CheckpointError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(CheckpointError::invalid(
+                path,
+                "not a checkpoint, or from another version",
+            ));
+        }
+        let checkpoint: Checkpoint = bincode::deserialize(&bytes[MAGIC.len()..])
+            .map_err(|err| CheckpointError::invalid(path, err.to_string()))?;
+        checkpoint
+            .validate()
+            .map_err(|message| CheckpointError::invalid(path, message))?;
+        Ok(checkpoint)
+    }
+
+    // Catches files that decode but would make no sense to resume, as after corruption
+    fn validate(&self) -> Result<(), String> {
+        let config = &self.config;
+        let framebuffer = &self.framebuffer;
+        if config.image_width == 0 || config.image_height == 0 || config.samples_per_pixel == 0 {
+            return Err("image size and samples per pixel must not be zero".to_string());
+        }
+        if framebuffer.width() != config.image_width
+            || framebuffer.height() != config.image_height
+            || !framebuffer.has_all_pixels()
+        {
+            return Err(format!(
+                "framebuffer doesn't match the {}x{} image",
+                config.image_width, config.image_height
+            ));
+        }
+        if !self.seeds.contains(&config.seed) {
+            return Err(format!(
+                "seeds don't include the seed {} rendered with",
+                config.seed
+            ));
+        }
+        if !is_pass_end(config.samples_per_pixel, self.samples_done) {
+            return Err(format!(
+                "{} samples per pixel is not where a pass of {} ends",
+                self.samples_done, config.samples_per_pixel
+            ));
+        }
+        let inside = |tile: &Tile| {
+            tile.x as u64 + tile.width as u64 <= config.image_width as u64
+                && tile.y as u64 + tile.height as u64 <= config.image_height as u64
+        };
+        if !self.tiles_done.iter().all(inside) {
+            return Err("tiles are outside the image".to_string());
+        }
+        Ok(())
+    }
+
+    /// Writes the checkpoint, replacing the file only once it is complete
+    pub fn save(&self, path: &Path) -> Result<(), CheckpointError> {
+        let mut bytes = MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self)
+            .map_err(|err| CheckpointError::invalid(path, err.to_string()))?;
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        fs::write(&partial, bytes)
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(|source| CheckpointError::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    /// Combines two renders of the same scene with different seeds into one with the samples
+    /// of both
+    ///
+    /// The result counts as a finished render of the samples both had, with the seed of self.
+    /// Renders interrupted in a pass have to be resumed to its end first.
+    pub fn merge(&self, other: &Checkpoint) -> Result<Checkpoint, String> {
+        if self.scene != other.scene {
+            return Err(format!(
+                "checkpoints are of different scenes, {} and {}",
+                self.scene, other.scene
+            ));
+        }
+        if let Some(seed) = other.seeds.iter().find(|seed| self.seeds.contains(seed)) {
+            return Err(format!(
+                "both checkpoints have the samples of seed {}",
+                seed
+            ));
+        }
+        let mut other_config = other.config.clone();
+        other_config.seed = self.config.seed;
+        other_config.samples_per_pixel = self.config.samples_per_pixel;
+        if other_config != self.config {
+            return Err("checkpoints have different render settings".to_string());
+        }
+        if self.framebuffer.width() != other.framebuffer.width()
+            || self.framebuffer.height() != other.framebuffer.height()
+        {
+            return Err("checkpoints have framebuffers of different sizes".to_string());
+        }
+        // Samples of a partial pass are in the framebuffer, but not in samples_done
+        if !self.tiles_done.is_empty() || !other.tiles_done.is_empty() {
+            return Err(
+                "a checkpoint was interrupted in the middle of a pass, resume it first".to_string(),
+            );
+        }
+
+        let mut framebuffer = self.framebuffer.clone();
+        framebuffer.merge(&other.framebuffer);
+        let samples_done = self
+            .samples_done
+            .checked_add(other.samples_done)
+            .ok_or("checkpoints have too many samples between them")?;
+        let mut seeds = [&self.seeds[..], &other.seeds[..]].concat();
+        seeds.sort_unstable();
+        Ok(Checkpoint {
+            scene: self.scene.clone(),
+            config: Config {
+                samples_per_pixel: samples_done,
+                ..self.config.clone()
+            },
+            seeds,
+            samples_done,
+            tiles_done: vec![],
+            framebuffer,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io { path: PathBuf, source: io::Error },
+    // Not a checkpoint this version can read
+    Invalid { path: PathBuf, message: String },
+}
+
+impl CheckpointError {
+    fn invalid<M: Into<String>>(path: &Path, message: M) -> Self {
+        CheckpointError::Invalid {
+            path: path.to_path_buf(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io { path, source } => {
+                write!(f, "checkpoint {}: {}", path.display(), source)
+            }
+            CheckpointError::Invalid { path, message } => {
+                write!(f, "checkpoint {}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CheckpointError::Io { source, .. } => Some(source),
+            CheckpointError::Invalid { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(seed: u64, samples_done: u32) -> Checkpoint {
+        let config = Config {
+            image_width: 8,
+            image_height: 4,
+            samples_per_pixel: 200,
+            seed,
+            ..Config::default()
+        };
+        Checkpoint {
+            scene: "cornell_box".to_string(),
+            framebuffer: Framebuffer::new(config.image_width, config.image_height),
+            config,
+            seeds: vec![seed],
+            samples_done,
+            tiles_done: vec![],
+        }
+    }
+
+    #[test]
+    fn validates_what_a_resume_relies_on() {
+        // Passes of 200 samples are 50, 50 and 100 long
+        for samples_done in [0, 50, 100, 200].iter() {
+            assert!(checkpoint(0, *samples_done).validate().is_ok());
+        }
+        assert!(checkpoint(0, 60).validate().is_err());
+
+        let mut wrong_size = checkpoint(0, 50);
+        wrong_size.framebuffer = Framebuffer::new(8, 5);
+        assert!(wrong_size.validate().is_err());
+
+        let mut tile_outside = checkpoint(0, 50);
+        tile_outside.tiles_done.push(Tile {
+            x: 4,
+            y: 0,
+            width: 8,
+            height: 4,
+        });
+        assert!(tile_outside.validate().is_err());
+
+        let mut seed_missing = checkpoint(0, 50);
+        seed_missing.seeds = vec![1];
+        assert!(seed_missing.validate().is_err());
+    }
+
+    #[test]
+    fn merges_finished_passes_of_different_seeds() {
+        let merged = checkpoint(1, 100).merge(&checkpoint(2, 200)).unwrap();
+        assert_eq!(merged.samples_done, 300);
+        assert_eq!(merged.config.samples_per_pixel, 300);
+        assert_eq!(merged.config.seed, 1);
+        assert_eq!(merged.seeds, vec![1, 2]);
+        assert!(merged.validate().is_ok());
+
+        assert!(checkpoint(1, 100).merge(&checkpoint(1, 100)).is_err());
+        // Seeds merged before count too, whichever side they are on
+        let merged = merged.merge(&checkpoint(3, 200)).unwrap();
+        assert_eq!(merged.seeds, vec![1, 2, 3]);
+        assert!(merged.merge(&checkpoint(2, 100)).is_err());
+        assert!(checkpoint(4, 100).merge(&merged).is_ok());
+        assert!(checkpoint(3, 100).merge(&merged).is_err());
+        let mut interrupted = checkpoint(2, 100);
+        interrupted.tiles_done.push(Tile {
+            x: 0,
+            y: 0,
+            width: 8,
+            height: 4,
+        });
+        assert!(checkpoint(1, 100).merge(&interrupted).is_err());
+        let mut wrong_size = checkpoint(2, 100);
+        wrong_size.framebuffer = Framebuffer::new(4, 8);
+        assert!(checkpoint(1, 100).merge(&wrong_size).is_err());
+    }
+}
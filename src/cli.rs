@@ -0,0 +1,413 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chrono::offset::Utc;
+use chrono::DateTime;
+use clap::{crate_version, App, Arg, ErrorKind};
+use image::ImageFormat;
+
+use pathtracer::*;
+
+type SceneFn = fn(&Config) -> (World, Arc<Camera>);
+
+const BUILT_IN_SCENES: [(&str, SceneFn); 3] = [
+    ("cornell_box", cornell_box),
+    ("simple_light", simple_light),
+    ("random_scene", random_scene),
+];
+
+const DEFAULT_SCENE: &str = "cornell_box";
+
+// Options that change the render, which a resumed render takes from its checkpoint
+const RENDER_SETTINGS: [&str; 8] = [
+    "width",
+    "height",
+    "spp",
+    "max-depth",
+    "seed",
+    "sampler",
+    "filter",
+    "noise",
+];
+
+const FORMATS: [&str; 5] = ["png", "jpeg", "bmp", "tga", "tiff"];
+
+pub enum SceneSource {
+    BuiltIn(SceneFn),
+    File(PathBuf),
+}
+
+/// Everything the renderer binary was asked to do on the command line
+pub struct Options {
+    // Built-in scene or scene file, if given
+    pub scene: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples_per_pixel: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub noise_target: Option<f64>,
+    pub threads: usize,
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: Option<PathBuf>,
+    // Checkpoints to combine instead of rendering, if any
+    pub merge: Vec<PathBuf>,
+}
+
+impl Options {
+    /// Parses the process arguments, printing usage and exiting if they are invalid
+    pub fn from_args() -> Self {
+        let scene_help = format!(
+            "Built-in scene ({}) or path to a TOML scene file [default: {}]",
+            BUILT_IN_SCENES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", "),
+            DEFAULT_SCENE
+        );
+        let app = App::new("pathtracer")
+            .version(crate_version!())
+            .about("Renders a scene with path tracing")
+            .arg(
+                Arg::with_name("scene")
+                    .help(&scene_help)
+                    .validator(is_scene),
+            )
+            .arg(
+                Arg::with_name("width")
+                    .long("width")
+                    .short("w")
+                    .takes_value(true)
+                    .help("Image width in pixels")
+                    .validator(is_positive::<u32>),
+            )
+            .arg(
+                Arg::with_name("height")
+                    .long("height")
+                    .short("H")
+                    .takes_value(true)
+                    .help("Image height in pixels")
+                    .validator(is_positive::<u32>),
+            )
+            .arg(
+                Arg::with_name("spp")
+                    .long("spp")
+                    .short("s")
+                    .takes_value(true)
+                    .help("Samples per pixel")
+                    .validator(is_positive::<u32>),
+            )
+            .arg(
+                Arg::with_name("max-depth")
+                    .long("max-depth")
+                    .short("d")
+                    .takes_value(true)
+                    .help("Maximum number of bounces per path")
+                    .validator(is_positive::<u32>),
+            )
+            .arg(
+                Arg::with_name("threads")
+                    .long("threads")
+                    .short("t")
+                    .takes_value(true)
+                    .help("Number of render threads [default: number of CPUs]")
+                    .validator(is_positive::<usize>),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .long("output")
+                    .short("o")
+                    .takes_value(true)
+                    .help(
+                        "Image to write, rewritten after every pass [default: output/<date>.png]",
+                    ),
+            )
+            .arg(
+                Arg::with_name("format")
+                    .long("format")
+                    .short("f")
+                    .takes_value(true)
+                    .possible_values(&FORMATS)
+                    .help("Image format [default: from the output extension, or png]"),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .help("Seed for the random numbers of the render")
+                    .validator(is_number::<u64>),
+            )
+            .arg(
+                Arg::with_name("sampler")
+                    .long("sampler")
+                    .takes_value(true)
+                    .possible_values(&SamplerKind::NAMES)
+                    .help("How the samples of a pixel are spread [default: sobol]"),
+            )
+            .arg(
+                Arg::with_name("filter")
+                    .long("filter")
+                    .takes_value(true)
+                    .possible_values(&FilterKind::NAMES)
+                    .help("How samples are weighted into nearby pixels [default: box]"),
+            )
+            .arg(
+                Arg::with_name("noise")
+                    .long("noise")
+                    .takes_value(true)
+                    .help(
+                        "Stop sampling pixels once their noise falls below this fraction of full \
+                         brightness, and write a heatmap of the samples taken next to the output",
+                    )
+                    .validator(is_positive_finite),
+            )
+            .arg(
+                Arg::with_name("checkpoint")
+                    .long("checkpoint")
+                    .takes_value(true)
+                    .help("Checkpoint to write after every pass, for --resume and --merge"),
+            )
+            .arg(
+                Arg::with_name("resume")
+                    .long("resume")
+                    .takes_value(true)
+                    .conflicts_with_all(&RENDER_SETTINGS)
+                    .help(
+                        "Continue the render of a checkpoint with its settings, writing further \
+                         checkpoints to it unless --checkpoint is given",
+                    ),
+            )
+            .arg(
+                Arg::with_name("merge")
+                    .long("merge")
+                    .takes_value(true)
+                    .multiple(true)
+                    .min_values(2)
+                    .conflicts_with_all(&RENDER_SETTINGS)
+                    .conflicts_with_all(&["scene", "resume"])
+                    .help(
+                        "Combine checkpoints of the same scene rendered with different seeds \
+                         into one image, and a checkpoint if --checkpoint is given",
+                    ),
+            );
+
+        let matches = match app.clone().get_matches_safe() {
+            Ok(matches) => matches,
+            // Clap only prints usage for some errors, so show it for bad values too
+            Err(err) if err.kind == ErrorKind::ValueValidation => exit_with_usage(&app, &err),
+            Err(err) => err.exit(),
+        };
+
+        // Values were checked by the validators, so parsing can't fail here
+        let parsed = |name| matches.value_of(name).map(|value| value.parse().unwrap());
+
+        // Pixels are counted in a u32
+        if let (Some(width), Some(height)) = (parsed("width"), parsed("height")) {
+            if u32::checked_mul(width, height).is_none() {
+                let err = clap::Error::with_description(
+                    &format!("an image of {}x{} pixels is too large", width, height),
+                    ErrorKind::ValueValidation,
+                );
+                exit_with_usage(&app, &err);
+            }
+        }
+
+        let output = match matches.value_of("output") {
+            Some(output) => PathBuf::from(output),
+            None => {
+                let datetime: DateTime<Utc> = SystemTime::now().into();
+                PathBuf::from(format!(
+                    "output/{}.png",
+                    datetime.format("%Y_%m_%d_%H_%M_%S")
+                ))
+            }
+        };
+        let format = match matches.value_of("format") {
+            Some(format) => format_from_name(format),
+            None => ImageFormat::from_path(&output).unwrap_or(ImageFormat::Png),
+        };
+
+        Self {
+            scene: matches.value_of("scene").map(String::from),
+            width: parsed("width"),
+            height: parsed("height"),
+            samples_per_pixel: parsed("spp"),
+            max_depth: parsed("max-depth"),
+            seed: matches.value_of("seed").map(|seed| seed.parse().unwrap()),
+            sampler: matches
+                .value_of("sampler")
+                .map(|sampler| sampler.parse().unwrap()),
+            filter: matches
+                .value_of("filter")
+                .map(|filter| filter.parse().unwrap()),
+            noise_target: matches
+                .value_of("noise")
+                .map(|noise| noise.parse().unwrap()),
+            threads: matches
+                .value_of("threads")
+                .map(|threads| threads.parse().unwrap())
+                .unwrap_or_else(|| {
+                    std::thread::available_parallelism().map_or(1, |threads| threads.get())
+                }),
+            output,
+            format,
+            checkpoint: matches.value_of("checkpoint").map(PathBuf::from),
+            resume: matches.value_of("resume").map(PathBuf::from),
+            merge: matches
+                .values_of("merge")
+                .map_or(vec![], |merge| merge.map(PathBuf::from).collect()),
+        }
+    }
+
+    /// Name of the scene to render, as given or the default
+    pub fn scene_name(&self) -> &str {
+        self.scene.as_deref().unwrap_or(DEFAULT_SCENE)
+    }
+
+    /// Loads the scene, with the render settings overridden by the command line
+    pub fn load_scene(&self) -> Result<(World, Arc<Camera>, Config), LoadError> {
+        match scene_source(self.scene_name()) {
+            SceneSource::BuiltIn(scene) => {
+                let config = self.override_config(Config::default());
+                if config
+                    .image_width
+                    .checked_mul(config.image_height)
+                    .is_none()
+                {
+                    return Err(LoadError::Invalid {
+                        path: self.scene_name().into(),
+                        message: "image has too many pixels".to_string(),
+                    });
+                }
+                let (world, camera) = scene(&config);
+                Ok((world, camera, config))
+            }
+            SceneSource::File(path) => {
+                let description = SceneDescription::open(path)?;
+                let config = self.override_config(description.config());
+                let (world, camera) = description.build(&config)?;
+                Ok((world, camera, config))
+            }
+        }
+    }
+
+    /// Where the sample heatmap of adaptive renders goes, next to the output as <name>_samples
+    pub fn heatmap_output(&self) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .map_or("output".into(), |stem| stem.to_string_lossy());
+        let mut name = format!("{}_samples", stem);
+        if let Some(extension) = self.output.extension() {
+            name = format!("{}.{}", name, extension.to_string_lossy());
+        }
+        self.output.with_file_name(name)
+    }
+
+    fn override_config(&self, mut config: Config) -> Config {
+        if let Some(width) = self.width {
+            config.image_width = width;
+        }
+        if let Some(height) = self.height {
+            config.image_height = height;
+        }
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            config.samples_per_pixel = samples_per_pixel;
+        }
+        if let Some(max_depth) = self.max_depth {
+            config.max_depth = max_depth;
+        }
+        if let Some(seed) = self.seed {
+            config.seed = seed;
+        }
+        if let Some(sampler) = self.sampler {
+            config.sampler = sampler;
+        }
+        if let Some(filter) = self.filter {
+            config.filter = filter;
+        }
+        if let Some(noise_target) = self.noise_target {
+            config.noise_target = Some(noise_target);
+        }
+        config
+    }
+}
+
+/// Loads a scene with the render settings of an earlier render, as when resuming it
+pub fn load_scene_with_config(
+    scene: &str,
+    config: &Config,
+) -> Result<(World, Arc<Camera>), LoadError> {
+    match scene_source(scene) {
+        SceneSource::BuiltIn(scene) => Ok(scene(config)),
+        SceneSource::File(path) => SceneDescription::open(path)?.build(config),
+    }
+}
+
+fn exit_with_usage(app: &App, err: &clap::Error) -> ! {
+    eprintln!("{}\n", err.message);
+    app.write_help(&mut std::io::stderr()).unwrap();
+    eprintln!();
+    std::process::exit(1);
+}
+
+fn scene_source(scene: &str) -> SceneSource {
+    match BUILT_IN_SCENES.iter().find(|(name, _)| *name == scene) {
+        Some((_, scene)) => SceneSource::BuiltIn(*scene),
+        None => SceneSource::File(PathBuf::from(scene)),
+    }
+}
+
+fn format_from_name(name: &str) -> ImageFormat {
+    match name {
+        "png" => ImageFormat::Png,
+        "jpeg" => ImageFormat::Jpeg,
+        "bmp" => ImageFormat::Bmp,
+        "tga" => ImageFormat::Tga,
+        "tiff" => ImageFormat::Tiff,
+        _ => unreachable!("clap only allows FORMATS"),
+    }
+}
+
+fn is_scene(scene: String) -> Result<(), String> {
+    if BUILT_IN_SCENES.iter().any(|(name, _)| *name == scene) || Path::new(&scene).is_file() {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' is neither a built-in scene nor a scene file",
+            scene
+        ))
+    }
+}
+
+fn is_number<T: std::str::FromStr>(value: String) -> Result<(), String> {
+    value
+        .parse::<T>()
+        .map(|_| ())
+        .map_err(|_| format!("'{}' is not a valid number", value))
+}
+
+fn is_positive<T: std::str::FromStr + Default + PartialOrd>(value: String) -> Result<(), String> {
+    is_number::<T>(value.clone())?;
+    // Also false for NaN
+    if value.parse::<T>().ok() > Some(T::default()) {
+        Ok(())
+    } else {
+        Err("must be greater than zero".to_string())
+    }
+}
+
+fn is_positive_finite(value: String) -> Result<(), String> {
+    is_positive::<f64>(value.clone())?;
+    if value.parse::<f64>().unwrap().is_finite() {
+        Ok(())
+    } else {
+        Err("must be finite".to_string())
+    }
+}
@@ -0,0 +1,263 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Weight of a sample in the pixels around it, by its offset from their centers
+///
+/// Offsets are in pixels. Samples further than the radius along either axis don't count.
+pub trait Filter {
+    fn radius(&self) -> f64;
+
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+/// The filters a render can be configured with, at their usual sizes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    // Every sample only counts in its own pixel
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FilterKind {
+    pub const NAMES: [&'static str; 5] = ["box", "tent", "gaussian", "mitchell", "blackman_harris"];
+
+    pub fn build(self) -> Box<dyn Filter + Send + Sync> {
+        match self {
+            Self::Box => Box::new(BoxFilter::new(0.5)),
+            Self::Tent => Box::new(TentFilter::new(1.0)),
+            Self::Gaussian => Box::new(GaussianFilter::new(1.5, 0.5)),
+            Self::Mitchell => Box::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)),
+            Self::BlackmanHarris => Box::new(BlackmanHarrisFilter::new(1.5)),
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "box" => Ok(Self::Box),
+            "tent" => Ok(Self::Tent),
+            "gaussian" => Ok(Self::Gaussian),
+            "mitchell" => Ok(Self::Mitchell),
+            "blackman_harris" => Ok(Self::BlackmanHarris),
+            _ => Err(format!("no filter called {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index = *self as usize;
+        f.write_str(Self::NAMES[index])
+    }
+}
+
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> Self {
+        assert!(radius > 0.0);
+        Self { radius }
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Weights falling linearly to zero at the radius
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> Self {
+        assert!(radius > 0.0);
+        Self { radius }
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        let tent = |offset: f64| (1.0 - offset.abs() / self.radius).max(0.0);
+        tent(x) * tent(y)
+    }
+}
+
+/// Gaussian with standard deviation sigma, lowered to reach zero at the radius
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+    // Value at the radius
+    edge: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        assert!(radius > 0.0 && sigma > 0.0);
+        Self {
+            radius,
+            sigma,
+            edge: gaussian(radius, sigma),
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        let weight = |offset: f64| (gaussian(offset, self.sigma) - self.edge).max(0.0);
+        weight(x) * weight(y)
+    }
+}
+
+/// Mitchell-Netravali cubic, sharper than a Gaussian with slightly negative lobes
+///
+/// B = C = 1/3 is what Mitchell and Netravali recommend.
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        assert!(radius > 0.0);
+        Self { radius, b, c }
+    }
+
+    // The cubic over [-2, 2]
+    fn mitchell(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+        if x < 1.0 {
+            ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0
+        } else if x < 2.0 {
+            ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.mitchell(2.0 * x / self.radius) * self.mitchell(2.0 * y / self.radius)
+    }
+}
+
+/// Four term Blackman-Harris window, close to a Gaussian but with less blur
+pub struct BlackmanHarrisFilter {
+    radius: f64,
+}
+
+impl BlackmanHarrisFilter {
+    pub fn new(radius: f64) -> Self {
+        assert!(radius > 0.0);
+        Self { radius }
+    }
+
+    fn window(&self, x: f64) -> f64 {
+        if x.abs() >= self.radius {
+            return 0.0;
+        }
+        // Position across the window, from 0 to 1
+        let t = 0.5 + x / (2.0 * self.radius);
+        0.35875 - 0.48829 * (2.0 * PI * t).cos() + 0.14128 * (4.0 * PI * t).cos()
+            - 0.01168 * (6.0 * PI * t).cos()
+    }
+}
+
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.window(x) * self.window(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::BlackmanHarris,
+    ];
+
+    #[test]
+    fn weights_vanish_beyond_the_radius() {
+        for kind in KINDS.iter() {
+            let filter = kind.build();
+            let radius = filter.radius();
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0, "{}", kind);
+            // Offsets from well outside the radius to well inside, and just past it
+            let offsets: Vec<f64> = (-40..=40)
+                .map(|i| i as f64 / 10.0)
+                .chain([radius + 1e-9, -radius - 1e-9].iter().copied())
+                .collect();
+            for &x in offsets.iter() {
+                for &y in offsets.iter() {
+                    let weight = filter.evaluate(x, y);
+                    if x.abs() > radius || y.abs() > radius {
+                        assert_eq!(weight, 0.0, "{} at ({}, {})", kind, x, y);
+                    }
+                    assert!(weight <= center, "{} at ({}, {})", kind, x, y);
+                    for mirrored in [filter.evaluate(-x, y), filter.evaluate(y, x)].iter() {
+                        assert!((weight - mirrored).abs() < 1e-12, "{}", kind);
+                    }
+                }
+            }
+            assert!(filter.evaluate(radius / 2.0, 0.0) > 0.0, "{}", kind);
+        }
+    }
+}
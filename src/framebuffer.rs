@@ -0,0 +1,353 @@
+use image::{Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Linear float image that samples are accumulated into
+///
+/// Each pixel is the filter weighted average of the samples around it. Rows are stored top to
+/// bottom, the way images are written.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    // Brightness of the samples taken in each pixel, and the sums of the weights and weighted
+    // samples around it
+    pixels: Vec<Pixel>,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Pixel {
+    luminance: RunningVariance,
+    weight: f64,
+    color: Color3,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![
+                Pixel {
+                    luminance: RunningVariance::default(),
+                    weight: 0.0,
+                    color: Vec3::zero(),
+                };
+                width as usize * height as usize
+            ],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Whether there is a pixel for every position, which a corrupt file may not have
+    pub(crate) fn has_all_pixels(&self) -> bool {
+        self.pixels.len() as u64 == self.width as u64 * self.height as u64
+    }
+
+    /// Adds the samples of a tile, dropping the weights that fall outside the image
+    pub fn add_tile(&mut self, film_tile: &FilmTile) {
+        let tile = &film_tile.tile;
+        for ((x, y), luminance) in tile.pixels().zip(&film_tile.luminance) {
+            let index = self.index(x, y);
+            self.pixels[index].luminance.merge(luminance);
+        }
+
+        let margin = film_tile.margin as i64;
+        let width = tile.width as i64 + 2 * margin;
+        for (i, (weight, color)) in film_tile.pixels.iter().enumerate() {
+            let x = tile.x as i64 + i as i64 % width - margin;
+            let y = tile.y as i64 + i as i64 / width - margin;
+            if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                continue;
+            }
+            let index = self.index(x as u32, y as u32);
+            let pixel = &mut self.pixels[index];
+            pixel.weight += weight;
+            pixel.color += *color;
+        }
+    }
+
+    /// Adds the samples of another render of the same size, as if they had been taken here
+    pub fn merge(&mut self, other: &Framebuffer) {
+        assert!(self.width == other.width && self.height == other.height);
+        for (pixel, other) in self.pixels.iter_mut().zip(&other.pixels) {
+            pixel.luminance.merge(&other.luminance);
+            pixel.weight += other.weight;
+            pixel.color += other.color;
+        }
+    }
+
+    /// Number of samples taken in a pixel, not counting those of its neighbors
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.pixels[self.index(x, y)].luminance.count()
+    }
+
+    /// Estimated noise of a pixel, as the standard error of its gamma corrected brightness
+    ///
+    /// Unknown, and so infinite, until the pixel has two samples. Its neighbors' samples aren't
+    /// counted, so this overestimates the noise of wide filters a little.
+    pub fn error(&self, x: u32, y: u32) -> f64 {
+        let luminance = &self.pixels[self.index(x, y)].luminance;
+        if luminance.count() < 2 {
+            return f64::INFINITY;
+        }
+        let standard_error = (luminance.variance() / luminance.count() as f64).sqrt();
+        // Gamma correction takes the square root, whose slope is 1 / (2 sqrt(mean)). The floor
+        // keeps black pixels with the odd bright sample from looking infinitely noisy.
+        standard_error / (2.0 * luminance.mean().max(1e-4).sqrt())
+    }
+
+    /// Weighted average of the samples around a pixel, black if there are none yet
+    pub fn color(&self, x: u32, y: u32) -> Color3 {
+        let pixel = self.pixels[self.index(x, y)];
+        if pixel.weight == 0.0 {
+            Vec3::zero()
+        } else {
+            pixel.color / pixel.weight
+        }
+    }
+
+    /// Gamma corrected 8 bit copy of the image
+    pub fn to_rgb_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| self.color(x, y).into())
+    }
+
+    /// Number of samples taken in each pixel, from black for none to white for the most
+    pub fn sample_heatmap(&self) -> RgbImage {
+        let most = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.luminance.count())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            heat_color(self.samples(x, y) as f64 / most as f64)
+        })
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height);
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+/// Samples taken in a tile, weighted by a filter onto its pixels and those just around it
+///
+/// Workers fill these in so filtering runs in parallel, and the framebuffer adds them up.
+pub struct FilmTile {
+    pub tile: Tile,
+    // Pixels this far outside the tile get weights too
+    margin: u32,
+    // Brightness of the samples taken in each pixel of the tile, for their noise estimates
+    luminance: Vec<RunningVariance>,
+    // Sums of the weights and weighted samples for the tile and its margin, row by row
+    pixels: Vec<(f64, Color3)>,
+}
+
+impl FilmTile {
+    pub fn new(tile: Tile, filter: &dyn Filter) -> Self {
+        // Samples lie within half a pixel of the center of the pixel they were taken in
+        let margin = (filter.radius() - 0.5).ceil().max(0.0) as u32;
+        let (width, height) = (tile.width + 2 * margin, tile.height + 2 * margin);
+        Self {
+            tile,
+            margin,
+            luminance: vec![RunningVariance::default(); (tile.width * tile.height) as usize],
+            pixels: vec![(0.0, Vec3::zero()); (width * height) as usize],
+        }
+    }
+
+    /// Whether no samples were taken, as in tiles skipped once a render was cancelled
+    pub fn is_empty(&self) -> bool {
+        self.luminance
+            .iter()
+            .all(|luminance| luminance.count() == 0)
+    }
+
+    /// Adds a sample taken in pixel (x, y) of the tile, which landed on the film at
+    /// (film_x, film_y), in pixels from the top left corner of the image
+    pub fn add_sample(
+        &mut self,
+        (x, y): (u32, u32),
+        (film_x, film_y): (f64, f64),
+        color: Color3,
+        filter: &dyn Filter,
+    ) {
+        let tile = &self.tile;
+        assert!(x >= tile.x && x < tile.x + tile.width && y >= tile.y && y < tile.y + tile.height);
+        self.luminance[((y - tile.y) * tile.width + x - tile.x) as usize].add(color.luminance());
+
+        let width = tile.width + 2 * self.margin;
+        // Neighbors within the margin, in the tile's padded coordinates
+        for padded_y in y - tile.y..=y - tile.y + 2 * self.margin {
+            for padded_x in x - tile.x..=x - tile.x + 2 * self.margin {
+                let center_x = (tile.x + padded_x) as f64 - self.margin as f64 + 0.5;
+                let center_y = (tile.y + padded_y) as f64 - self.margin as f64 + 0.5;
+                let weight = filter.evaluate(film_x - center_x, film_y - center_y);
+                if weight != 0.0 {
+                    let (weight_sum, color_sum) =
+                        &mut self.pixels[(padded_y * width + padded_x) as usize];
+                    *weight_sum += weight;
+                    *color_sum += weight * color;
+                }
+            }
+        }
+    }
+}
+
+// Black through blue, red and yellow to white, for t from 0 to 1
+fn heat_color(t: f64) -> Rgb<u8> {
+    const STOPS: [Color3; 5] = [
+        Vec3(0.0, 0.0, 0.0),
+        Vec3(0.0, 0.0, 1.0),
+        Vec3(1.0, 0.0, 0.0),
+        Vec3(1.0, 1.0, 0.0),
+        Vec3(1.0, 1.0, 1.0),
+    ];
+    let position = t.clam(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let stop = (position as usize).min(STOPS.len() - 2);
+    let color = STOPS[stop] + (STOPS[stop + 1] - STOPS[stop]) * (position - stop as f64);
+    Rgb([
+        (255.0 * color.x()) as u8,
+        (255.0 * color.y()) as u8,
+        (255.0 * color.z()) as u8,
+    ])
+}
+
+/// Mean and variance of a stream of values, updated one at a time with Welford's method
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct RunningVariance {
+    count: u32,
+    mean: f64,
+    // Sum of squared differences from the mean
+    m2: f64,
+}
+
+impl RunningVariance {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Combines the values of other with these, as if they had all been added here
+    pub fn merge(&mut self, other: &RunningVariance) {
+        if other.count == 0 {
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count as f64;
+        self.count = count;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance, zero until there are two values
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::BlackmanHarris,
+    ];
+
+    const OFFSETS: [(f64, f64); 4] = [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7), (0.3, 0.95)];
+
+    // Four samples per pixel of a 12 by 10 image, split into tiles of tile_size
+    fn splat<F: Fn(f64, f64) -> Color3>(kind: FilterKind, tile_size: u32, color: F) -> Framebuffer {
+        let filter = kind.build();
+        let mut framebuffer = Framebuffer::new(12, 10);
+        for tile in Tile::hilbert_order(12, 10, tile_size) {
+            let mut film_tile = FilmTile::new(tile, &*filter);
+            for (x, y) in tile.pixels() {
+                for (dx, dy) in OFFSETS.iter() {
+                    let (film_x, film_y) = (x as f64 + dx, y as f64 + dy);
+                    film_tile.add_sample((x, y), (film_x, film_y), color(film_x, film_y), &*filter);
+                }
+            }
+            framebuffer.add_tile(&film_tile);
+        }
+        framebuffer
+    }
+
+    // Weighted average of every sample in the image, for pixel (x, y)
+    fn filtered<F: Fn(f64, f64) -> Color3>(kind: FilterKind, color: F, x: u32, y: u32) -> Color3 {
+        let filter = kind.build();
+        let (center_x, center_y) = (x as f64 + 0.5, y as f64 + 0.5);
+        let mut weight_sum = 0.0;
+        let mut color_sum = Vec3::zero();
+        for sample_y in 0..10 {
+            for sample_x in 0..12 {
+                for (dx, dy) in OFFSETS.iter() {
+                    let (film_x, film_y) = (sample_x as f64 + dx, sample_y as f64 + dy);
+                    let weight = filter.evaluate(film_x - center_x, film_y - center_y);
+                    weight_sum += weight;
+                    color_sum += weight * color(film_x, film_y);
+                }
+            }
+        }
+        color_sum / weight_sum
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        for &kind in KINDS.iter() {
+            let framebuffer = splat(kind, 4, |_, _| Vec3(0.2, 0.5, 0.9));
+            for y in 0..10 {
+                for x in 0..12 {
+                    let color = framebuffer.color(x, y);
+                    assert!((color - Vec3(0.2, 0.5, 0.9)).length() < 1e-12, "{}", kind);
+                    assert_eq!(framebuffer.samples(x, y), 4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_reach_pixels_across_tile_edges() {
+        let gradient = |x: f64, y: f64| Vec3(x / 12.0, y / 10.0, (x * y).sin().abs());
+        for &kind in KINDS.iter() {
+            for &tile_size in [1, 3, 5, 16].iter() {
+                let framebuffer = splat(kind, tile_size, gradient);
+                for y in 0..10 {
+                    for x in 0..12 {
+                        let difference =
+                            (framebuffer.color(x, y) - filtered(kind, gradient, x, y)).length();
+                        assert!(difference < 1e-12, "{} in tiles of {}", kind, tile_size);
+                    }
+                }
+            }
+        }
+    }
+}
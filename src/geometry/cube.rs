@@ -53,7 +53,7 @@ impl Cube {
             (min.x(), min.x()),
             (min.y(), max.y()),
             (min.z(), max.z()),
-            material.clone(),
+            material,
         )));
 
         Arc::new(Self { min, max, sides })
@@ -61,11 +61,19 @@ impl Cube {
 }
 
 impl Hittable for Cube {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(r, t_min, t_max)
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        self.sides.hit(r, t_min, t_max, rng)
     }
 
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         Some(AABB::new(self.min, self.max))
     }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        self.sides.occluded(r, t_min, t_max, rng)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.sides.emitters()
+    }
 }
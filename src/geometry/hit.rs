@@ -2,8 +2,9 @@ use std::sync::Arc;
 
 use crate::*;
 
-pub struct HitRecord {
-    pub material: Arc<dyn Material>,
+/// Where a ray hits an object, borrowing the material from the object that was hit
+pub struct HitRecord<'a> {
+    pub material: &'a dyn Material,
     pub position: Vec3,
     pub normal: Vec3,
     pub distance: f64,
@@ -12,8 +13,8 @@ pub struct HitRecord {
     pub v: f64,
 }
 
-impl HitRecord {
-    pub fn from_material(material: Arc<dyn Material>) -> Self {
+impl<'a> HitRecord<'a> {
+    pub fn from_material(material: &'a dyn Material) -> Self {
         Self {
             position: Vec3::zero(),
             normal: Vec3::zero(),
@@ -41,10 +42,37 @@ impl HitRecord {
     }
 }
 
+/// Randomness, as in participating media, is drawn from the rng of the sample being traced,
+/// so renders are reproducible. Light sampling takes its values from the sample's `Sampler`.
 pub trait Hittable {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>>;
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
+
+    // Whether anything is hit between t_min and t_max, without finding the closest hit
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        self.hit(r, t_min, t_max, rng).is_some()
+    }
+
+    // Solid angle density of sampling r.direction from r.origin with `random`
+    fn pdf_value(&self, _r: &Ray) -> f64 {
+        0.0
+    }
+
+    // Direction from origin towards a point on this object, chosen by u in [0, 1)^2
+    fn random(&self, _origin: &Point3, _time: f64, _u: (f64, f64)) -> Vec3 {
+        Vec3(1.0, 0.0, 0.0)
+    }
+
+    // Every light emitting part of this object, as something that can be sampled on its own
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        vec![]
+    }
+
+    // Statistics of every BVH in this object
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        vec![]
+    }
 }
 
 pub struct FlipFace(pub Arc<dyn Hittable + Send + Sync>);
@@ -56,18 +84,40 @@ impl FlipFace {
 }
 
 impl Hittable for FlipFace {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.0.hit(r, t_min, t_max).and_then(|hit| {
-            Some(HitRecord {
-                front_face: !hit.front_face,
-                ..hit
-            })
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        self.0.hit(r, t_min, t_max, rng).map(|hit| HitRecord {
+            front_face: !hit.front_face,
+            ..hit
         })
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.0.bounding_box(t0, t1)
     }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        self.0.occluded(r, t_min, t_max, rng)
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        self.0.pdf_value(r)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        self.0.random(origin, time, u)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.0
+            .emitters()
+            .into_iter()
+            .map(|emitter| FlipFace::new(emitter) as Arc<dyn Hittable + Send + Sync>)
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.0.bvh_stats()
+    }
 }
 
 pub struct Translation {
@@ -82,19 +132,47 @@ impl Translation {
 }
 
 impl Hittable for Translation {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
         let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
-        self.object.hit(&moved_r, t_min, t_max).and_then(|mut hit| {
+        self.object.hit(&moved_r, t_min, t_max, rng).map(|mut hit| {
+            // The normal already faces the ray and keeps its front_face
             hit.position += self.offset;
-            hit.set_face_normal(&moved_r, hit.normal); // Redundant?
-            Some(hit)
+            hit
         })
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.object
             .bounding_box(t0, t1)
-            .and_then(|bbox| Some(AABB::new(bbox.min + self.offset, bbox.max + self.offset)))
+            .map(|bbox| AABB::new(bbox.min + self.offset, bbox.max + self.offset))
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
+        self.object.occluded(&moved_r, t_min, t_max, rng)
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let moved_r = Ray::new(r.origin - self.offset, r.direction, r.time);
+        self.object.pdf_value(&moved_r)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        self.object.random(&(*origin - self.offset), time, u)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.object
+            .emitters()
+            .into_iter()
+            .map(|emitter| {
+                Translation::new(emitter, self.offset) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.object.bvh_stats()
     }
 }
 
@@ -115,9 +193,15 @@ pub struct YRotation {
 impl YRotation {
     pub fn new(object: Arc<dyn Hittable + Send + Sync>, degrees: f64) -> Arc<Self> {
         let radians = degrees.to_radians();
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = object.bounding_box(0.0, 1.0).and_then(|bbox| {
+        Self::from_sin_cos(object, radians.sin(), radians.cos())
+    }
+
+    fn from_sin_cos(
+        object: Arc<dyn Hittable + Send + Sync>,
+        sin_theta: f64,
+        cos_theta: f64,
+    ) -> Arc<Self> {
+        let bbox = object.bounding_box(0.0, 1.0).map(|bbox| {
             let mut min = Vec3::infinity();
             let mut max = Vec3::neg_infinity();
 
@@ -135,7 +219,7 @@ impl YRotation {
                 }
             }
 
-            Some(AABB::new(min, max))
+            AABB::new(min, max)
         });
 
         Arc::new(Self {
@@ -147,39 +231,81 @@ impl YRotation {
     }
 }
 
-impl Hittable for YRotation {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = r.origin;
-        let mut direction = r.direction;
-
-        origin[0] = self.cos_theta * r.origin[0] - self.sin_theta * r.origin[2];
-        origin[2] = self.sin_theta * r.origin[0] + self.cos_theta * r.origin[2];
+impl YRotation {
+    // World space to object space
+    fn to_object(&self, p: Vec3) -> Vec3 {
+        Vec3(
+            self.cos_theta * p[0] - self.sin_theta * p[2],
+            p[1],
+            self.sin_theta * p[0] + self.cos_theta * p[2],
+        )
+    }
 
-        direction[0] = self.cos_theta * r.direction[0] - self.sin_theta * r.direction[2];
-        direction[2] = self.sin_theta * r.direction[0] + self.cos_theta * r.direction[2];
+    // Object space to world space
+    fn to_world(&self, p: Vec3) -> Vec3 {
+        Vec3(
+            self.cos_theta * p[0] + self.sin_theta * p[2],
+            p[1],
+            -self.sin_theta * p[0] + self.cos_theta * p[2],
+        )
+    }
+}
 
-        let rotated_r = Ray::new(origin, direction, r.time);
+impl Hittable for YRotation {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        let rotated_r = Ray::new(
+            self.to_object(r.origin),
+            self.to_object(r.direction),
+            r.time,
+        );
 
         self.object
-            .hit(&rotated_r, t_min, t_max)
-            .and_then(|mut hit| {
-                let mut position = hit.position;
-                let mut normal = hit.normal;
+            .hit(&rotated_r, t_min, t_max, rng)
+            .map(|mut hit| {
+                hit.position = self.to_world(hit.position);
+                hit.normal = self.to_world(hit.normal);
+                hit
+            })
+    }
 
-                position[0] = self.cos_theta * hit.position[0] + self.sin_theta * hit.position[2];
-                position[2] = -self.sin_theta * hit.position[0] + self.cos_theta * hit.position[2];
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.bbox.clone()
+    }
 
-                normal[0] = self.cos_theta * hit.normal[0] + self.sin_theta * hit.normal[2];
-                normal[2] = -self.sin_theta * hit.normal[0] + self.cos_theta * hit.normal[2];
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        let rotated_r = Ray::new(
+            self.to_object(r.origin),
+            self.to_object(r.direction),
+            r.time,
+        );
+        self.object.occluded(&rotated_r, t_min, t_max, rng)
+    }
 
-                hit.position = position;
-                hit.set_face_normal(&rotated_r, normal);
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let rotated_r = Ray::new(
+            self.to_object(r.origin),
+            self.to_object(r.direction),
+            r.time,
+        );
+        self.object.pdf_value(&rotated_r)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        self.to_world(self.object.random(&self.to_object(*origin), time, u))
+    }
 
-                Some(hit)
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.object
+            .emitters()
+            .into_iter()
+            .map(|emitter| {
+                Self::from_sin_cos(emitter, self.sin_theta, self.cos_theta)
+                    as Arc<dyn Hittable + Send + Sync>
             })
+            .collect()
     }
 
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        self.bbox.clone()
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.object.bvh_stats()
     }
 }
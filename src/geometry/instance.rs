@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use crate::*;
+
+/// Shared geometry placed in the world by an affine transform
+pub struct Instance {
+    object: Arc<dyn Hittable + Send + Sync>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, transform: Transform) -> Arc<Self> {
+        Arc::new(Self { object, transform })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        transformed_hit(&*self.object, &self.transform, r, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.object
+            .bounding_box(t0, t1)
+            .map(|bbox| self.transform.bounding_box(&bbox))
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        transformed_occluded(&*self.object, &self.transform, r, t_min, t_max, rng)
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        transformed_pdf_value(&*self.object, &self.transform, r)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        transformed_random(&*self.object, &self.transform, origin, time, u)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.object
+            .emitters()
+            .into_iter()
+            .map(|emitter| {
+                Instance::new(emitter, self.transform) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.object.bvh_stats()
+    }
+}
+
+// Hits object, which lives in the object space of transform, with a world space ray
+pub(crate) fn transformed_hit<'a>(
+    object: &'a dyn Hittable,
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rng: &mut SampleRng,
+) -> Option<HitRecord<'a>> {
+    let object_r = transform.inverse().ray(r);
+    // Rays have unit directions, so distances along them change with the scale
+    let scale = transform.inverse().vector(r.direction).length();
+    object
+        .hit(&object_r, t_min * scale, t_max * scale, rng)
+        .map(|mut hit| {
+            // The inverse transpose keeps the normal facing the ray, so front_face holds
+            hit.position = transform.point(hit.position);
+            hit.normal = transform.normal(hit.normal);
+            hit.distance /= scale;
+            hit
+        })
+}
+
+pub(crate) fn transformed_occluded(
+    object: &dyn Hittable,
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rng: &mut SampleRng,
+) -> bool {
+    let object_r = transform.inverse().ray(r);
+    let scale = transform.inverse().vector(r.direction).length();
+    object.occluded(&object_r, t_min * scale, t_max * scale, rng)
+}
+
+pub(crate) fn transformed_pdf_value(object: &dyn Hittable, transform: &Transform, r: &Ray) -> f64 {
+    let object_r = transform.inverse().ray(r);
+    // Solid angles change by |det| / |M u|^3 when directions u are mapped through M
+    let stretch = transform.vector(object_r.direction).length();
+    let determinant = transform.matrix().determinant3().abs();
+    object.pdf_value(&object_r) * stretch * stretch * stretch / determinant
+}
+
+pub(crate) fn transformed_random(
+    object: &dyn Hittable,
+    transform: &Transform,
+    origin: &Point3,
+    time: f64,
+    u: (f64, f64),
+) -> Vec3 {
+    let object_origin = transform.inverse().point(*origin);
+    transform.vector(object.random(&object_origin, time, u))
+}
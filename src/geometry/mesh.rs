@@ -0,0 +1,200 @@
+use crate::*;
+
+/// Vertex buffers shared by every face of a mesh
+///
+/// Normals and uvs are either empty or hold one entry per position
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    fn vertices(&self, face: usize) -> [Point3; 3] {
+        let [a, b, c] = self.faces[face];
+        [self.positions[a], self.positions[b], self.positions[c]]
+    }
+
+    fn normals(&self, face: usize) -> Option<[Vec3; 3]> {
+        if self.normals.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.faces[face];
+        Some([self.normals[a], self.normals[b], self.normals[c]])
+    }
+
+    fn uvs(&self, face: usize) -> Option<[(f64, f64); 3]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.faces[face];
+        Some([self.uvs[a], self.uvs[b], self.uvs[c]])
+    }
+}
+
+/// Indexed triangle mesh with its own BVH over its faces
+///
+/// Clones and instances share the buffers and the BVH.
+#[derive(Clone)]
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    faces: Arc<BvhTree>,
+    // Running total of face areas, for picking faces proportional to their size
+    area_cdf: Arc<Vec<f64>>,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl TriangleMesh {
+    /// Fails on meshes that can't be rendered or sampled: without faces or area, with
+    /// indices past the positions, or with normals or uvs for only some of them
+    pub fn new(
+        data: MeshData,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Result<Arc<Self>, String> {
+        if data.faces.is_empty() {
+            return Err("mesh has no faces".to_string());
+        }
+        if !data.normals.is_empty() && data.normals.len() != data.positions.len() {
+            return Err(format!(
+                "mesh has {} normals for {} positions",
+                data.normals.len(),
+                data.positions.len()
+            ));
+        }
+        if !data.uvs.is_empty() && data.uvs.len() != data.positions.len() {
+            return Err(format!(
+                "mesh has {} uvs for {} positions",
+                data.uvs.len(),
+                data.positions.len()
+            ));
+        }
+        if let Some(&index) = data
+            .faces
+            .iter()
+            .flatten()
+            .find(|&&index| index >= data.positions.len())
+        {
+            return Err(format!(
+                "mesh face uses position {}, there are {}",
+                index,
+                data.positions.len()
+            ));
+        }
+
+        let data = Arc::new(data);
+
+        let mut total_area = 0.0;
+        let area_cdf = (0..data.faces.len())
+            .map(|face| {
+                total_area += triangle_area(&data.vertices(face));
+                total_area
+            })
+            .collect();
+        // Also catches positions that aren't finite
+        if !(total_area > 0.0 && total_area.is_finite()) {
+            return Err("mesh has no area".to_string());
+        }
+
+        let boxes: Vec<AABB> = (0..data.faces.len())
+            .map(|face| triangle_box(&data.vertices(face)))
+            .collect();
+
+        Ok(Arc::new(Self {
+            data,
+            faces: Arc::new(BvhTree::new(&boxes)),
+            area_cdf: Arc::new(area_cdf),
+            material,
+        }))
+    }
+
+    pub fn area(&self) -> f64 {
+        *self.area_cdf.last().unwrap()
+    }
+
+    // Closest hit, with the face it is on
+    fn hit_faces(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord<'_>, usize)> {
+        let mut closest_face = 0;
+        let hit = self.faces.hit(r, t_min, t_max, |face, t_max| {
+            let hit = hit_triangle(
+                &self.data.vertices(face),
+                self.data.normals(face).as_ref(),
+                self.data.uvs(face).as_ref(),
+                &*self.material,
+                r,
+                t_min,
+                t_max,
+            )?;
+            // Each hit found is closer than the ones before it
+            closest_face = face;
+            Some(hit)
+        })?;
+        Some((hit, closest_face))
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        self.hit_faces(r, t_min, t_max).map(|(hit, _)| hit)
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> bool {
+        self.faces.occluded(r, t_min, t_max, |face| {
+            intersect_triangle(&self.data.vertices(face), r, t_min, t_max).is_some()
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.faces.bounding_box().clone())
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        // The ray may pass through several faces, each of which could have been sampled.
+        // Points are picked by area, so the density depends on the face normal, not the
+        // shading normal of smooth meshes
+        let mut pdf = 0.0;
+        let mut t_min = 0.001;
+        while let Some((hit, face)) = self.hit_faces(r, t_min, f64::INFINITY) {
+            let normal = triangle_normal(&self.data.vertices(face));
+            let cosine = r.direction.dot(normal).abs();
+            if cosine > 0.0 {
+                pdf += hit.distance * hit.distance / (cosine * self.area());
+            }
+            t_min = hit.distance + 0.0001;
+        }
+        pdf
+    }
+
+    fn random(&self, origin: &Point3, _time: f64, u: (f64, f64)) -> Vec3 {
+        let target = u.0 * self.area();
+        let face = self
+            .area_cdf
+            .partition_point(|&area| area < target)
+            .min(self.area_cdf.len() - 1);
+        // Where target falls within the face's share of the area, back in [0, 1)
+        let start = if face == 0 {
+            0.0
+        } else {
+            self.area_cdf[face - 1]
+        };
+        let face_area = self.area_cdf[face] - start;
+        let u0 = if face_area > 0.0 {
+            ((target - start) / face_area).min(1.0)
+        } else {
+            0.0
+        };
+        random_on_triangle(&self.data.vertices(face), (u0, u.1)) - *origin
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        if self.material.is_emissive() {
+            vec![Arc::new(self.clone())]
+        } else {
+            vec![]
+        }
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        vec![self.faces.stats().clone()]
+    }
+}
@@ -1,9 +1,17 @@
 mod cube;
 mod hit;
+mod instance;
+mod mesh;
+mod motion;
 mod rectangle;
 mod sphere;
+mod triangle;
 
 pub use cube::*;
 pub use hit::*;
+pub use instance::*;
+pub use mesh::*;
+pub use motion::*;
 pub use rectangle::*;
 pub use sphere::*;
+pub use triangle::*;
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use crate::*;
+
+/// Where an animated object is at a point in time
+#[derive(Copy, Debug, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f64) -> Self {
+        Self {
+            time,
+            translation: Vec3::zero(),
+            rotation: Quaternion::identity(),
+            scale: Vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn translate(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn rotate(mut self, axis: Vec3, degrees: f64) -> Self {
+        self.rotation = Quaternion::from_axis_angle(axis, degrees);
+        self
+    }
+
+    pub fn scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_parts(self.translation, self.rotation, self.scale)
+    }
+}
+
+/// Shared geometry moved by transforms interpolated between keyframes, using the ray's time
+///
+/// Translation and scale are interpolated linearly and rotation with slerp.
+/// Outside the keyframes the object stays at the first or last one.
+pub struct MotionTransform {
+    object: Arc<dyn Hittable + Send + Sync>,
+    keyframes: Arc<Vec<Keyframe>>,
+}
+
+impl MotionTransform {
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, keyframes: Vec<Keyframe>) -> Arc<Self> {
+        assert!(!keyframes.is_empty());
+        assert!(keyframes.windows(2).all(|pair| pair[0].time < pair[1].time));
+        Arc::new(Self {
+            object,
+            keyframes: Arc::new(keyframes),
+        })
+    }
+
+    fn keyframe(&self, time: f64) -> Keyframe {
+        let keyframes = &self.keyframes;
+        let next = keyframes.partition_point(|keyframe| keyframe.time <= time);
+        if next == 0 {
+            return keyframes[0];
+        }
+        if next == keyframes.len() {
+            return keyframes[next - 1];
+        }
+
+        let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+        let t = (time - a.time) / (b.time - a.time);
+        Keyframe {
+            time,
+            translation: a.translation + (b.translation - a.translation) * t,
+            rotation: a.rotation.slerp(&b.rotation, t),
+            scale: a.scale + (b.scale - a.scale) * t,
+        }
+    }
+
+    pub fn transform(&self, time: f64) -> Transform {
+        self.keyframe(time).transform()
+    }
+}
+
+impl Hittable for MotionTransform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        transformed_hit(&*self.object, &self.transform(r.time), r, t_min, t_max, rng)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let bbox = self.object.bounding_box(t0, t1)?;
+
+        // Boxes at closely spaced times, and at every keyframe so slerps aren't cut short
+        const STEPS: usize = 32;
+        let mut times: Vec<f64> = (0..=STEPS)
+            .map(|i| t0 + (t1 - t0) * i as f64 / STEPS as f64)
+            .chain(
+                self.keyframes
+                    .iter()
+                    .map(|keyframe| keyframe.time)
+                    .filter(|time| *time > t0 && *time < t1),
+            )
+            .collect();
+        times.sort_by(f64::total_cmp);
+
+        // Furthest the object reaches from its origin
+        let mut radius: f64 = 0.0;
+        for x in [bbox.min.x(), bbox.max.x()].iter() {
+            for y in [bbox.min.y(), bbox.max.y()].iter() {
+                for z in [bbox.min.z(), bbox.max.z()].iter() {
+                    radius = radius.max(Vec3(*x, *y, *z).length());
+                }
+            }
+        }
+
+        let mut result: Option<AABB> = None;
+        for pair in times.windows(2) {
+            let (a, b) = (self.keyframe(pair[0]), self.keyframe(pair[1]));
+            // Between two times points swing out on an arc, at most this far past the end boxes
+            let max_scale = (0..3)
+                .map(|axis| a.scale[axis].abs().max(b.scale[axis].abs()))
+                .fold(0.0, f64::max);
+            let bulge = radius * max_scale * (1.0 - (a.rotation.angle_to(&b.rotation) / 2.0).cos());
+            let bulge = Vec3(bulge, bulge, bulge);
+
+            let swept = a
+                .transform()
+                .bounding_box(&bbox)
+                .surrounding_box(&b.transform().bounding_box(&bbox));
+            let swept = AABB::new(swept.min - bulge, swept.max + bulge);
+            result = Some(match result {
+                Some(result) => result.surrounding_box(&swept),
+                None => swept,
+            });
+        }
+        result.or_else(|| Some(self.transform(t0).bounding_box(&bbox)))
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> bool {
+        transformed_occluded(&*self.object, &self.transform(r.time), r, t_min, t_max, rng)
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        transformed_pdf_value(&*self.object, &self.transform(r.time), r)
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        transformed_random(&*self.object, &self.transform(time), origin, time, u)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        self.object
+            .emitters()
+            .into_iter()
+            .map(|emitter| {
+                Arc::new(Self {
+                    object: emitter,
+                    keyframes: self.keyframes.clone(),
+                }) as Arc<dyn Hittable + Send + Sync>
+            })
+            .collect()
+    }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.object.bvh_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(bbox: &AABB) -> Vec<Point3> {
+        (0..8)
+            .map(|i| {
+                let pick = |axis: usize| {
+                    if i & (1 << axis) == 0 {
+                        bbox.min[axis]
+                    } else {
+                        bbox.max[axis]
+                    }
+                };
+                Vec3(pick(0), pick(1), pick(2))
+            })
+            .collect()
+    }
+
+    fn contains(bbox: &AABB, p: Point3) -> bool {
+        (0..3).all(|axis| bbox.min[axis] - 1e-9 <= p[axis] && p[axis] <= bbox.max[axis] + 1e-9)
+    }
+
+    // Off the origin, so rotations swing it around
+    fn moving_cube() -> Arc<MotionTransform> {
+        let cube = Cube::new(
+            Vec3(1.0, 0.0, 0.0),
+            Vec3(2.0, 0.5, 0.5),
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        );
+        MotionTransform::new(
+            cube,
+            vec![
+                Keyframe::new(0.2),
+                Keyframe::new(0.5)
+                    .translate(Vec3(3.0, 1.0, 0.0))
+                    .rotate(Vec3(0.0, 1.0, 0.0), 170.0)
+                    .scale(Vec3(2.0, 1.0, 1.0)),
+                Keyframe::new(0.9)
+                    .translate(Vec3(-2.0, 0.0, 4.0))
+                    .rotate(Vec3(0.0, 0.0, 1.0), 90.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let motion = moving_cube();
+        let origin = Vec3::zero();
+        let at = |time: f64| motion.transform(time).point(origin);
+        assert!((at(0.35) - Vec3(1.5, 0.5, 0.0)).length() < 1e-9);
+        assert!((at(0.7) - Vec3(0.5, 0.5, 2.0)).length() < 1e-9);
+        // Held at the first and last keyframes outside of them
+        assert!(at(0.0).length() < 1e-9);
+        assert!((at(1.0) - Vec3(-2.0, 0.0, 4.0)).length() < 1e-9);
+        let x = Vec3(1.0, 0.0, 0.0);
+        let halfway = motion.transform(0.35).vector(x) / 1.5;
+        assert!(
+            (halfway - Vec3(85f64.to_radians().cos(), 0.0, -85f64.to_radians().sin())).length()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn bounding_box_contains_the_object_over_the_shutter() {
+        let motion = moving_cube();
+        let object_box = motion.object.bounding_box(0.0, 1.0).unwrap();
+        for &(t0, t1) in [(0.0, 1.0), (0.3, 0.6), (0.55, 0.6)].iter() {
+            let bbox = motion.bounding_box(t0, t1).unwrap();
+            for i in 0..=1000 {
+                let time = t0 + (t1 - t0) * i as f64 / 1000.0;
+                let transform = motion.transform(time);
+                for corner in corners(&object_box) {
+                    let p = transform.point(corner);
+                    assert!(contains(&bbox, p), "{:?} at time {} is outside", p, time);
+                }
+            }
+        }
+    }
+}
@@ -11,6 +11,7 @@ impl From<&str> for Axis {
     }
 }
 
+#[derive(Clone)]
 pub struct AxisRectangle {
     material: Arc<dyn Material + Send + Sync>,
     hort: (f64, f64),
@@ -28,7 +29,7 @@ impl AxisRectangle {
         material: Arc<dyn Material + Send + Sync>,
     ) -> Arc<Self> {
         let axis = axis.into();
-        let (face, hort, vert) = match axis.into() {
+        let (face, hort, vert) = match axis {
             Axis::X => (x.0, y, z),
             Axis::Y => (y.0, x, z),
             Axis::Z => (z.0, x, y),
@@ -39,33 +40,19 @@ impl AxisRectangle {
 
         Arc::new(Self {
             material,
-            axis: axis.into(),
+            axis,
             face,
             hort,
             vert,
         })
     }
-}
 
-impl Hittable for AxisRectangle {
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        Some(match self.axis {
-            Axis::X => AABB::new(
-                Vec3(self.face - 0.0001, self.hort.0, self.vert.0),
-                Vec3(self.face + 0.0001, self.hort.1, self.vert.1),
-            ),
-            Axis::Y => AABB::new(
-                Vec3(self.hort.0, self.face - 0.0001, self.vert.0),
-                Vec3(self.hort.1, self.face + 0.0001, self.vert.1),
-            ),
-            Axis::Z => AABB::new(
-                Vec3(self.hort.0, self.vert.0, self.face - 0.0001),
-                Vec3(self.hort.1, self.vert.1, self.face + 0.0001),
-            ),
-        })
+    pub fn area(&self) -> f64 {
+        (self.hort.1 - self.hort.0) * (self.vert.1 - self.vert.0)
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // Distance and in-plane coordinates of the intersection, if between t_min and t_max
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
         let distance = match self.axis {
             Axis::X => (self.face - r.origin.x()) / r.direction.x(),
             Axis::Y => (self.face - r.origin.y()) / r.direction.y(),
@@ -89,13 +76,36 @@ impl Hittable for AxisRectangle {
         if vert < self.vert.0 || vert > self.vert.1 {
             return None;
         }
+        Some((distance, hort, vert))
+    }
+}
+
+impl Hittable for AxisRectangle {
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(match self.axis {
+            Axis::X => AABB::new(
+                Vec3(self.face - 0.0001, self.hort.0, self.vert.0),
+                Vec3(self.face + 0.0001, self.hort.1, self.vert.1),
+            ),
+            Axis::Y => AABB::new(
+                Vec3(self.hort.0, self.face - 0.0001, self.vert.0),
+                Vec3(self.hort.1, self.face + 0.0001, self.vert.1),
+            ),
+            Axis::Z => AABB::new(
+                Vec3(self.hort.0, self.vert.0, self.face - 0.0001),
+                Vec3(self.hort.1, self.vert.1, self.face + 0.0001),
+            ),
+        })
+    }
 
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        let (distance, hort, vert) = self.intersect(r, t_min, t_max)?;
         let face = match self.axis {
             Axis::X => r.origin.x() + distance * r.direction.x(),
             Axis::Y => r.origin.y() + distance * r.direction.y(),
             Axis::Z => r.origin.z() + distance * r.direction.z(),
         };
-        let mut hit = HitRecord::from_material(self.material.clone());
+        let mut hit = HitRecord::from_material(&*self.material);
         hit.u = (hort - self.hort.1) / (self.hort.1 - self.hort.0);
         hit.v = (vert - self.vert.1) / (self.vert.1 - self.vert.0);
         hit.position = match self.axis {
@@ -114,4 +124,42 @@ impl Hittable for AxisRectangle {
         );
         Some(hit)
     }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if let Some((distance, _, _)) = self.intersect(r, 0.001, f64::INFINITY) {
+            let distance_squared = distance * distance;
+            let cosine = match self.axis {
+                Axis::X => r.direction.x(),
+                Axis::Y => r.direction.y(),
+                Axis::Z => r.direction.z(),
+            }
+            .abs();
+            distance_squared / (cosine * self.area())
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, _time: f64, u: (f64, f64)) -> Vec3 {
+        let hort = self.hort.0 + u.0 * (self.hort.1 - self.hort.0);
+        let vert = self.vert.0 + u.1 * (self.vert.1 - self.vert.0);
+        let point = match self.axis {
+            Axis::X => Vec3(self.face, hort, vert),
+            Axis::Y => Vec3(hort, self.face, vert),
+            Axis::Z => Vec3(hort, vert, self.face),
+        };
+        point - *origin
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        if self.material.is_emissive() {
+            vec![Arc::new(self.clone())]
+        } else {
+            vec![]
+        }
+    }
 }
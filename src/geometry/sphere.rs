@@ -1,6 +1,8 @@
 use crate::*;
+use std::f64::consts::PI;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct Sphere {
     pub center: (Point3, Point3),
     pub time: (f64, f64),
@@ -32,11 +34,9 @@ impl Sphere {
         self.center.0
             + ((time - self.time.0) / (self.time.1 - self.time.0)) * (self.center.1 - self.center.0)
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let center = self.center(r.time);
+    // Distance to the nearest intersection between t_min and t_max
+    fn intersect(&self, r: &Ray, center: Point3, t_min: f64, t_max: f64) -> Option<f64> {
         let oc = r.origin - center;
         let a = r.direction.length_squared();
         let half_b = oc.dot(r.direction);
@@ -53,19 +53,32 @@ impl Hittable for Sphere {
             };
 
             if distance < t_max && distance > t_min {
-                let mut hit_record = HitRecord::from_material(self.material.clone());
-                hit_record.position = r.at(distance);
-                hit_record.distance = distance;
-                let outward_normal = (hit_record.position - center) / self.radius;
-                hit_record.set_face_normal(r, outward_normal);
-                let (u, v) = get_sphere_uv(&((hit_record.position - center) / self.radius));
-                hit_record.u = u;
-                hit_record.v = v;
-                return Some(hit_record);
+                return Some(distance);
             }
         }
         None
     }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        let center = self.center(r.time);
+        let distance = self.intersect(r, center, t_min, t_max)?;
+        let mut hit_record = HitRecord::from_material(&*self.material);
+        hit_record.position = r.at(distance);
+        hit_record.distance = distance;
+        let outward_normal = (hit_record.position - center) / self.radius;
+        hit_record.set_face_normal(r, outward_normal);
+        let (u, v) = get_sphere_uv(&((hit_record.position - center) / self.radius));
+        hit_record.u = u;
+        hit_record.v = v;
+        Some(hit_record)
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> bool {
+        self.intersect(r, self.center(r.time), t_min, t_max)
+            .is_some()
+    }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         let radius_vec = Vec3(self.radius.abs(), self.radius.abs(), self.radius.abs());
@@ -75,10 +88,48 @@ impl Hittable for Sphere {
         let bound_box_1 = AABB::new(center_1 - radius_vec, center_1 + radius_vec);
         Some(bound_box_0.surrounding_box(&bound_box_1))
     }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if self
+            .intersect(r, self.center(r.time), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center(r.time) - r.origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // From inside, every direction hits the sphere
+            return 0.25 / PI;
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, time: f64, u: (f64, f64)) -> Vec3 {
+        let direction = self.center(time) - *origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::sample_unit_vector(u);
+        }
+        let uvw = ONB::build_from_w(&direction);
+        uvw.local(&Vec3::sample_to_sphere(self.radius, distance_squared, u))
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        if self.material.is_emissive() {
+            vec![Arc::new(self.clone())]
+        } else {
+            vec![]
+        }
+    }
 }
 
 pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
-    use std::f64::consts::{FRAC_1_PI, FRAC_PI_2, PI};
+    use std::f64::consts::{FRAC_1_PI, FRAC_PI_2};
     let phi = p.z().atan2(p.x());
     let theta = p.y().asin();
 
@@ -88,6 +139,7 @@ pub fn get_sphere_uv(p: &Vec3) -> (f64, f64) {
     )
 }
 
+#[derive(Clone)]
 pub struct SkySphere {
     pub material: Arc<dyn Material + Send + Sync>,
 }
@@ -101,24 +153,24 @@ impl SkySphere {
 }
 
 impl Hittable for SkySphere {
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
         None
     }
 
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if t_max == std::f64::INFINITY {
-            let mut hit = HitRecord::from_material(self.material.clone());
+    // Infinitely far away, so nothing is ever behind it
+    fn occluded(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rng: &mut SampleRng) -> bool {
+        false
+    }
+
+    fn hit(&self, r: &Ray, _t_min: f64, t_max: f64, _rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        if t_max == f64::INFINITY {
             let (u, v) = get_sphere_uv(&r.direction);
-            hit.normal = -r.direction;
-            hit.position = r.direction;
-            hit.u = u;
-            hit.v = v;
             Some(HitRecord {
-                material: self.material.clone(),
+                material: &*self.material,
                 normal: -r.direction,
                 front_face: true,
                 position: r.direction,
-                distance: std::f64::INFINITY,
+                distance: f64::INFINITY,
                 u,
                 v,
             })
@@ -126,4 +178,16 @@ impl Hittable for SkySphere {
             None
         }
     }
+
+    fn pdf_value(&self, _r: &Ray) -> f64 {
+        0.25 / PI
+    }
+
+    fn random(&self, _origin: &Point3, _time: f64, u: (f64, f64)) -> Vec3 {
+        Vec3::sample_unit_vector(u)
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        vec![Arc::new(self.clone())]
+    }
 }
@@ -0,0 +1,183 @@
+use crate::*;
+
+#[derive(Clone)]
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Triangle {
+    pub fn new(
+        a: Point3,
+        b: Point3,
+        c: Point3,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Self::with_attributes([a, b, c], None, None, material)
+    }
+
+    // Per-vertex normals give smooth shading, without uvs the barycentric coordinates are used
+    pub fn with_attributes(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            vertices,
+            normals,
+            uvs,
+            material,
+        })
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        hit_triangle(
+            &self.vertices,
+            self.normals.as_ref(),
+            self.uvs.as_ref(),
+            &*self.material,
+            r,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f64, t_max: f64, _rng: &mut SampleRng) -> bool {
+        intersect_triangle(&self.vertices, r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(triangle_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if let Some((distance, _, _)) = intersect_triangle(&self.vertices, r, 0.001, f64::INFINITY)
+        {
+            let cosine = r.direction.dot(triangle_normal(&self.vertices)).abs();
+            distance * distance / (cosine * triangle_area(&self.vertices))
+        } else {
+            0.0
+        }
+    }
+
+    fn random(&self, origin: &Point3, _time: f64, u: (f64, f64)) -> Vec3 {
+        random_on_triangle(&self.vertices, u) - *origin
+    }
+
+    fn emitters(&self) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        if self.material.is_emissive() {
+            vec![Arc::new(self.clone())]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// Möller-Trumbore intersection, shared by lone triangles and mesh faces
+pub(crate) fn hit_triangle<'a>(
+    vertices: &[Point3; 3],
+    normals: Option<&[Vec3; 3]>,
+    uvs: Option<&[(f64, f64); 3]>,
+    material: &'a dyn Material,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'a>> {
+    let (distance, b1, b2) = intersect_triangle(vertices, r, t_min, t_max)?;
+    let b0 = 1.0 - b1 - b2;
+
+    let mut hit = HitRecord::from_material(material);
+    hit.distance = distance;
+    hit.position = r.at(distance);
+
+    // Which side we hit is decided by the real surface, shading normals only bend the light
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let geometric_normal = edge1.cross(edge2).unit_vector();
+    hit.set_face_normal(r, geometric_normal);
+    if let Some(normals) = normals {
+        let shading_normal = (b0 * normals[0] + b1 * normals[1] + b2 * normals[2]).unit_vector();
+        hit.normal = if hit.front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+    }
+
+    let (u, v) = match uvs {
+        Some(uvs) => (
+            b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0,
+            b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1,
+        ),
+        None => (b1, b2),
+    };
+    hit.u = u;
+    hit.v = v;
+    Some(hit)
+}
+
+// Möller-Trumbore, giving the distance and the barycentric coordinates of the second and third vertex
+pub(crate) fn intersect_triangle(
+    vertices: &[Point3; 3],
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let p = r.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inv_determinant = 1.0 / determinant;
+
+    let s = r.origin - vertices[0];
+    let b1 = s.dot(p) * inv_determinant;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let b2 = r.direction.dot(q) * inv_determinant;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inv_determinant;
+    if distance < t_min || distance > t_max {
+        return None;
+    }
+    Some((distance, b1, b2))
+}
+
+pub(crate) fn triangle_box(vertices: &[Point3; 3]) -> AABB {
+    // Padded so axis aligned triangles still have some thickness
+    let padding = Vec3(0.0001, 0.0001, 0.0001);
+    let min = vertices[0].min(&vertices[1]).min(&vertices[2]);
+    let max = vertices[0].max(&vertices[1]).max(&vertices[2]);
+    AABB::new(min - padding, max + padding)
+}
+
+pub(crate) fn triangle_normal(vertices: &[Point3; 3]) -> Vec3 {
+    (vertices[1] - vertices[0])
+        .cross(vertices[2] - vertices[0])
+        .unit_vector()
+}
+
+pub(crate) fn triangle_area(vertices: &[Point3; 3]) -> f64 {
+    0.5 * (vertices[1] - vertices[0])
+        .cross(vertices[2] - vertices[0])
+        .length()
+}
+
+// Uniformly distributed point on the triangle's surface
+pub(crate) fn random_on_triangle(vertices: &[Point3; 3], u: (f64, f64)) -> Point3 {
+    let s = u.0.sqrt();
+    let b0 = 1.0 - s;
+    let b1 = u.1 * s;
+    b0 * vertices[0] + b1 * vertices[1] + (1.0 - b0 - b1) * vertices[2]
+}
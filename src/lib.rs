@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use image::*;
+
+mod acceleration;
+mod camera;
+mod checkpoint;
+mod filter;
+mod framebuffer;
+mod geometry;
+mod loaders;
+mod material;
+mod matrix;
+mod onb;
+mod pdf;
+mod perlin;
+mod ray;
+mod renderer;
+mod rng;
+mod sampler;
+mod scenes;
+mod texture;
+mod tile;
+mod util;
+mod vec3;
+mod volume;
+mod worker;
+mod world;
+
+pub use acceleration::*;
+pub use camera::*;
+pub use checkpoint::*;
+pub use filter::*;
+pub use framebuffer::*;
+pub use geometry::*;
+pub use loaders::*;
+pub use material::*;
+pub use matrix::*;
+pub use onb::*;
+pub use pdf::*;
+pub use perlin::*;
+pub use ray::*;
+pub use renderer::*;
+pub use rng::*;
+pub use sampler::*;
+pub use scenes::*;
+pub use texture::*;
+pub use tile::*;
+pub use util::*;
+pub use vec3::*;
+pub use volume::*;
+pub use worker::*;
+pub use world::*;
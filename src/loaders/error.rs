@@ -0,0 +1,81 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    // Well formed, but does not describe something we can build
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn parse<P: Into<PathBuf>, M: Into<String>>(
+        path: P,
+        line: usize,
+        message: M,
+    ) -> Self {
+        LoadError::Parse {
+            path: path.into(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn invalid<P: Into<PathBuf>, M: Into<String>>(path: P, message: M) -> Self {
+        LoadError::Invalid {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => {
+                write!(f, "could not read {}: {}", path.display(), source)
+            }
+            LoadError::Image { path, source } => {
+                write!(f, "could not load image {}: {}", path.display(), source)
+            }
+            LoadError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            LoadError::Toml { path, source } => write!(f, "{}: {}", path.display(), source),
+            LoadError::Invalid { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Image { source, .. } => Some(source),
+            LoadError::Toml { source, .. } => Some(source),
+            LoadError::Parse { .. } | LoadError::Invalid { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,10 @@
+mod error;
+mod mtl;
+mod obj;
+mod parse;
+mod scene;
+
+pub use error::*;
+pub use mtl::*;
+pub use obj::*;
+pub use scene::*;
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::parse::*;
+use crate::*;
+
+pub type MaterialLibrary = HashMap<String, Arc<dyn Material + Send + Sync>>;
+
+// Everything we read from a newmtl block
+struct MtlEntry {
+    name: String,
+    line: usize,
+    diffuse: Color3,
+    specular: Color3,
+    emission: Color3,
+    shininess: f64,
+    refract_index: f64,
+    dissolve: f64,
+    illum: u32,
+    diffuse_map: Option<PathBuf>,
+    emission_map: Option<PathBuf>,
+}
+
+impl MtlEntry {
+    fn new(name: &str, line: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            line,
+            diffuse: Vec3(0.8, 0.8, 0.8),
+            specular: Vec3::zero(),
+            emission: Vec3::zero(),
+            shininess: 0.0,
+            refract_index: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+            emission_map: None,
+        }
+    }
+}
+
+/// Reads a Wavefront material library
+///
+/// Emissive entries become DiffuseLight, transparent ones Dielectric,
+/// mirrors Metal and everything else Lambertian.
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<MaterialLibrary, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| LoadError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_mtl(&source, path)
+}
+
+// Texture maps are looked up next to path
+fn parse_mtl(source: &str, path: &Path) -> Result<MaterialLibrary, LoadError> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut entries: Vec<MtlEntry> = vec![];
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (keyword, args) = match statement(text) {
+            Some(statement) => statement,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            if args.len() != 1 {
+                return Err(LoadError::parse(
+                    path,
+                    line,
+                    "newmtl needs exactly one name",
+                ));
+            }
+            entries.push(MtlEntry::new(args[0], line));
+            continue;
+        }
+
+        let entry = match entries.last_mut() {
+            Some(entry) => entry,
+            None => {
+                return Err(LoadError::parse(
+                    path,
+                    line,
+                    format!("'{}' before any newmtl", keyword),
+                ))
+            }
+        };
+        match keyword {
+            "Kd" => entry.diffuse = parse_vec3(&args, path, line)?,
+            "Ks" => entry.specular = parse_vec3(&args, path, line)?,
+            "Ke" => entry.emission = parse_vec3(&args, path, line)?,
+            "Ns" => entry.shininess = parse_floats(&args, 1, false, path, line)?[0],
+            "Ni" => entry.refract_index = parse_floats(&args, 1, false, path, line)?[0],
+            "d" => entry.dissolve = parse_floats(&args, 1, false, path, line)?[0],
+            "Tr" => entry.dissolve = 1.0 - parse_floats(&args, 1, false, path, line)?[0],
+            "illum" => {
+                entry.illum = args
+                    .first()
+                    .and_then(|token| token.parse().ok())
+                    .ok_or_else(|| LoadError::parse(path, line, "illum needs a model number"))?
+            }
+            "map_Kd" => entry.diffuse_map = Some(map_path(directory, &args, path, line)?),
+            "map_Ke" => entry.emission_map = Some(map_path(directory, &args, path, line)?),
+            // Ambient, bump maps and the like have no counterpart here
+            _ => {}
+        }
+    }
+
+    let mut textures: HashMap<PathBuf, Arc<ImageTexture>> = HashMap::new();
+    let mut library = MaterialLibrary::new();
+    for entry in entries {
+        if library.contains_key(&entry.name) {
+            return Err(LoadError::parse(
+                path,
+                entry.line,
+                format!("material '{}' is defined twice", entry.name),
+            ));
+        }
+        let material = into_material(&entry, &mut textures)?;
+        library.insert(entry.name, material);
+    }
+    Ok(library)
+}
+
+// Texture maps may carry options before the file name, which always comes last
+fn map_path(
+    directory: &Path,
+    args: &[&str],
+    path: &Path,
+    line: usize,
+) -> Result<PathBuf, LoadError> {
+    match args.last() {
+        Some(file) => Ok(directory.join(file)),
+        None => Err(LoadError::parse(
+            path,
+            line,
+            "texture map needs a file name",
+        )),
+    }
+}
+
+fn load_texture(
+    path: &Path,
+    textures: &mut HashMap<PathBuf, Arc<ImageTexture>>,
+) -> Result<Arc<ImageTexture>, LoadError> {
+    if let Some(texture) = textures.get(path) {
+        return Ok(texture.clone());
+    }
+    let texture = ImageTexture::try_from_file(path).map_err(|source| LoadError::Image {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    textures.insert(path.to_path_buf(), texture.clone());
+    Ok(texture)
+}
+
+fn into_material(
+    entry: &MtlEntry,
+    textures: &mut HashMap<PathBuf, Arc<ImageTexture>>,
+) -> Result<Arc<dyn Material + Send + Sync>, LoadError> {
+    let is_black = |color: Color3| color.x() <= 0.0 && color.y() <= 0.0 && color.z() <= 0.0;
+
+    if let Some(map) = &entry.emission_map {
+        return Ok(DiffuseLight::from_texture(load_texture(map, textures)?));
+    }
+    if !is_black(entry.emission) {
+        return Ok(DiffuseLight::from_texture(Arc::new(SolidColor(
+            entry.emission,
+        ))));
+    }
+
+    // Illumination models 4, 6, 7 and 9 are the glass ones
+    if entry.dissolve < 1.0 || [4, 6, 7, 9].contains(&entry.illum) {
+        return Ok(Dielectric::new(entry.refract_index));
+    }
+
+    if entry.illum == 3 || (is_black(entry.diffuse) && !is_black(entry.specular)) {
+        // Rough approximation of a Phong exponent as fuzz
+        let fuzz = (2.0 / (entry.shininess + 2.0)).sqrt();
+        return Ok(Metal::new(entry.specular, fuzz));
+    }
+
+    match &entry.diffuse_map {
+        Some(map) => Ok(Lambertian::from_texture(load_texture(map, textures)?)),
+        None => Ok(Lambertian::from_color3(entry.diffuse)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<MaterialLibrary, LoadError> {
+        parse_mtl(source, Path::new("test.mtl"))
+    }
+
+    #[test]
+    fn reads_every_material() {
+        let library = parse(
+            "# A comment\nnewmtl light\nKe 4 4 4\n\nnewmtl red\nKd 0.8 0.1 0.1 # red\n\
+             newmtl glass\nillum 7\nNi 1.5\nnewmtl mirror\nKd 0 0 0\nKs 0.9 0.9 0.9\nNs 900\n",
+        )
+        .unwrap();
+        let mut names: Vec<&str> = library.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(names, vec!["glass", "light", "mirror", "red"]);
+        assert!(library["light"].is_emissive());
+        assert!(!library["red"].is_emissive());
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let cases = [
+            ("Kd 1 1 1\n", 1),
+            ("newmtl\n", 1),
+            ("newmtl a b\n", 1),
+            ("newmtl a\n\nKd 1 x 1\n", 3),
+            ("newmtl a\nKd 1 1\n", 2),
+            ("newmtl a\nNs 1 2\n", 2),
+            ("newmtl a\nillum\n", 2),
+            ("newmtl a\nmap_Kd\n", 2),
+            ("newmtl a\nKd 1 1 1\nnewmtl a\n", 3),
+        ];
+        for (source, line) in cases.iter() {
+            match parse(source) {
+                Err(LoadError::Parse { line: found, .. }) => {
+                    assert_eq!(found, *line, "{:?}", source)
+                }
+                Err(err) => panic!("expected a parse error for {:?}, got {}", source, err),
+                Ok(_) => panic!("expected a parse error for {:?}", source),
+            }
+        }
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::parse::*;
+use crate::*;
+
+// Position, uv and normal indices of one face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// Faces of one group that share a material, with their own vertex numbering
+struct MeshBuilder {
+    group: String,
+    material: Option<(String, usize)>,
+    corners: HashMap<Corner, usize>,
+    vertices: Vec<Corner>,
+    faces: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(group: String, material: Option<(String, usize)>) -> Self {
+        Self {
+            group,
+            material,
+            corners: HashMap::new(),
+            vertices: vec![],
+            faces: vec![],
+        }
+    }
+
+    fn vertex(&mut self, corner: Corner) -> usize {
+        let vertices = &mut self.vertices;
+        *self.corners.entry(corner).or_insert_with(|| {
+            vertices.push(corner);
+            vertices.len() - 1
+        })
+    }
+
+    // Corners are only given normals or uvs when every one of them has them
+    fn build(
+        self,
+        positions: &[Point3],
+        uvs: &[(f64, f64)],
+        normals: &[Vec3],
+        material: Arc<dyn Material + Send + Sync>,
+        path: &Path,
+    ) -> Result<Arc<TriangleMesh>, LoadError> {
+        let group = self.group;
+        let all_uvs = self.vertices.iter().all(|corner| corner.1.is_some());
+        let all_normals = self.vertices.iter().all(|corner| corner.2.is_some());
+        let data = MeshData {
+            positions: self
+                .vertices
+                .iter()
+                .map(|corner| positions[corner.0])
+                .collect(),
+            uvs: if all_uvs {
+                self.vertices
+                    .iter()
+                    .map(|corner| uvs[corner.1.unwrap()])
+                    .collect()
+            } else {
+                vec![]
+            },
+            normals: if all_normals {
+                self.vertices
+                    .iter()
+                    .map(|corner| normals[corner.2.unwrap()])
+                    .collect()
+            } else {
+                vec![]
+            },
+            faces: self.faces,
+        };
+        TriangleMesh::new(data, material)
+            .map_err(|err| LoadError::invalid(path, format!("group '{}': {}", group, err)))
+    }
+}
+
+// Everything read from an OBJ file, before it is built into meshes
+struct ObjContents {
+    positions: Vec<Point3>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    library: MaterialLibrary,
+    builders: Vec<MeshBuilder>,
+}
+
+/// Reads a Wavefront OBJ file, and any material libraries it references, into meshes
+///
+/// Every group and material gets its own mesh. Faces without usemtl use `default_material`.
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    default_material: Arc<dyn Material + Send + Sync>,
+) -> Result<HitList, LoadError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| LoadError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    build_meshes(parse_obj(&source, path)?, default_material, path)
+}
+
+// Material libraries are looked up next to path
+fn parse_obj(source: &str, path: &Path) -> Result<ObjContents, LoadError> {
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Point3> = vec![];
+    let mut uvs: Vec<(f64, f64)> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut library = MaterialLibrary::new();
+    let mut builders = vec![MeshBuilder::new("default".to_string(), None)];
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (keyword, args) = match statement(text) {
+            Some(statement) => statement,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&args, path, line)?),
+            "vt" => {
+                let values = parse_floats(&args, 1, true, path, line)?;
+                let v = match args.get(1) {
+                    Some(token) => parse_f64(token, path, line)?,
+                    None => 0.0,
+                };
+                uvs.push((values[0], v));
+            }
+            "vn" => {
+                let normal = parse_vec3(&args, path, line)?;
+                if !(normal.length() > 0.0 && normal.length().is_finite()) {
+                    return Err(LoadError::parse(
+                        path,
+                        line,
+                        "normal has to have a finite, non-zero length",
+                    ));
+                }
+                normals.push(normal.unit_vector());
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(LoadError::parse(
+                        path,
+                        line,
+                        format!("face needs at least 3 vertices, found {}", args.len()),
+                    ));
+                }
+                let corners = args
+                    .iter()
+                    .map(|token| {
+                        parse_corner(
+                            token,
+                            (positions.len(), uvs.len(), normals.len()),
+                            path,
+                            line,
+                        )
+                    })
+                    .collect::<Result<Vec<Corner>, LoadError>>()?;
+
+                // Fan out polygons around their first corner
+                let builder = builders.last_mut().unwrap();
+                let first = builder.vertex(corners[0]);
+                for pair in corners[1..].windows(2) {
+                    let face = [first, builder.vertex(pair[0]), builder.vertex(pair[1])];
+                    builder.faces.push(face);
+                }
+            }
+            "g" | "o" => {
+                let material = builders.last().unwrap().material.clone();
+                start_mesh(&mut builders, args.join(" "), material);
+            }
+            "usemtl" => {
+                if args.len() != 1 {
+                    return Err(LoadError::parse(
+                        path,
+                        line,
+                        "usemtl needs exactly one name",
+                    ));
+                }
+                let group = builders.last().unwrap().group.clone();
+                start_mesh(&mut builders, group, Some((args[0].to_string(), line)));
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(LoadError::parse(path, line, "mtllib needs a file name"));
+                }
+                for file in args {
+                    library.extend(load_mtl(directory.join(file))?);
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are ignored
+            _ => {}
+        }
+    }
+
+    Ok(ObjContents {
+        positions,
+        uvs,
+        normals,
+        library,
+        builders,
+    })
+}
+
+fn build_meshes(
+    contents: ObjContents,
+    default_material: Arc<dyn Material + Send + Sync>,
+    path: &Path,
+) -> Result<HitList, LoadError> {
+    let ObjContents {
+        positions,
+        uvs,
+        normals,
+        library,
+        builders,
+    } = contents;
+    let mut meshes = HitList::new();
+    for builder in builders
+        .into_iter()
+        .filter(|builder| !builder.faces.is_empty())
+    {
+        let material = match &builder.material {
+            None => default_material.clone(),
+            Some((name, line)) => library.get(name).cloned().ok_or_else(|| {
+                LoadError::parse(path, *line, format!("material '{}' is not defined", name))
+            })?,
+        };
+        meshes.add(builder.build(&positions, &uvs, &normals, material, path)?);
+    }
+    Ok(meshes)
+}
+
+// Reuses the current mesh if nothing was added to it yet
+fn start_mesh(builders: &mut Vec<MeshBuilder>, group: String, material: Option<(String, usize)>) {
+    let current = builders.last_mut().unwrap();
+    if current.faces.is_empty() {
+        current.group = group;
+        current.material = material;
+    } else {
+        builders.push(MeshBuilder::new(group, material));
+    }
+}
+
+// Reads v, v/vt, v//vn or v/vt/vn, with 1-based or negative, relative indices
+fn parse_corner(
+    token: &str,
+    (position_count, uv_count, normal_count): (usize, usize, usize),
+    path: &Path,
+    line: usize,
+) -> Result<Corner, LoadError> {
+    let mut parts = token.split('/');
+    let position = resolve_index(parts.next(), position_count, "vertex", path, line)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        part => Some(resolve_index(
+            part,
+            uv_count,
+            "texture coordinate",
+            path,
+            line,
+        )?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        part => Some(resolve_index(part, normal_count, "normal", path, line)?),
+    };
+    if parts.next().is_some() {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("face vertex '{}' has too many parts", token),
+        ));
+    }
+    Ok((position, uv, normal))
+}
+
+fn resolve_index(
+    token: Option<&str>,
+    count: usize,
+    kind: &str,
+    path: &Path,
+    line: usize,
+) -> Result<usize, LoadError> {
+    let token = token.unwrap_or("");
+    let index: i64 = token.parse().map_err(|_| {
+        LoadError::parse(
+            path,
+            line,
+            format!("expected a {} index, found '{}'", kind, token),
+        )
+    })?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!(
+                "{} index {} is out of range, there are {}",
+                kind, index, count
+            ),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> ObjContents {
+        match parse_obj(source, Path::new("test.obj")) {
+            Ok(contents) => contents,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // Corners of every face of a mesh, by their indices in the file
+    fn face_corners(builder: &MeshBuilder) -> Vec<[Corner; 3]> {
+        builder
+            .faces
+            .iter()
+            .map(|face| face.map(|vertex| builder.vertices[vertex]))
+            .collect()
+    }
+
+    fn positions(builder: &MeshBuilder) -> Vec<[usize; 3]> {
+        face_corners(builder)
+            .iter()
+            .map(|face| face.map(|corner| corner.0))
+            .collect()
+    }
+
+    fn error_line(result: Result<impl Sized, LoadError>) -> usize {
+        match result {
+            Err(LoadError::Parse { line, .. }) => line,
+            Err(err) => panic!("expected a parse error, got {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn fans_out_polygons() {
+        let contents = parse(&format!("{}f 1 2 3\nf 1 2 3 4\n", SQUARE));
+        assert_eq!(contents.builders.len(), 1);
+        assert_eq!(
+            positions(&contents.builders[0]),
+            vec![[0, 1, 2], [0, 1, 2], [0, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn resolves_negative_indices_against_the_vertices_so_far() {
+        let contents = parse(&format!("{}f -4 -3 -2\n{}f -3 -2 -1 4\n", SQUARE, SQUARE));
+        assert_eq!(
+            positions(&contents.builders[0]),
+            vec![[0, 1, 2], [5, 6, 7], [5, 7, 3]]
+        );
+    }
+
+    #[test]
+    fn reads_uv_and_normal_indices() {
+        let contents = parse(&format!(
+            "{}vt 0 0\nvt 1 0\nvt 1 1 0.5\nvn 0 0 2\n\
+             f 1/1/1 2/2/1 3/3/1\nf 1//1 2//-1 3//1\nf 1/-3 2/2 3/3\n",
+            SQUARE
+        ));
+        let faces = face_corners(&contents.builders[0]);
+        assert_eq!(
+            faces[0],
+            [
+                (0, Some(0), Some(0)),
+                (1, Some(1), Some(0)),
+                (2, Some(2), Some(0))
+            ]
+        );
+        assert_eq!(
+            faces[1],
+            [(0, None, Some(0)), (1, None, Some(0)), (2, None, Some(0))]
+        );
+        assert_eq!(
+            faces[2],
+            [(0, Some(0), None), (1, Some(1), None), (2, Some(2), None)]
+        );
+        assert_eq!(contents.uvs[2], (1.0, 1.0));
+        let normal = contents.normals[0];
+        assert_eq!((normal.x(), normal.y(), normal.z()), (0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn splits_meshes_by_group_and_material() {
+        let contents = parse(&format!(
+            "{}f 1 2 3\ng lid\nf 1 2 3\nusemtl red\nf 1 2 3\ng base\nf 1 2 3\n",
+            SQUARE
+        ));
+        let meshes: Vec<(&str, Option<&str>)> = contents
+            .builders
+            .iter()
+            .map(|builder| {
+                let material = builder.material.as_ref().map(|(name, _)| name.as_str());
+                (builder.group.as_str(), material)
+            })
+            .collect();
+        assert_eq!(
+            meshes,
+            vec![
+                ("default", None),
+                ("lid", None),
+                ("lid", Some("red")),
+                ("base", Some("red"))
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let cases = [
+            ("v 0 0 0\nv 1 0 0\nf 1 2\n", 3),
+            ("v 0 0 0\nf 1 1 2\n", 2),
+            ("v 0 0 0\n\nf 0 1 1\n", 3),
+            ("v 0 0 0\nf -2 1 1\n", 2),
+            ("v 0 0 0\nf 1/1 1 1\n", 2),
+            ("v 0 0 0\nvn 0 0 1\nf 1//1/1 1 1\n", 3),
+            ("v 0 0 0\nf a 1 1\n", 2),
+            ("# comment\nv 1 x 2\n", 2),
+            ("v 1 2\n", 1),
+            ("vn 0 0 1\nvn 0 0 0\n", 2),
+            ("usemtl\n", 1),
+            ("mtllib\n", 1),
+        ];
+        for (source, line) in cases.iter() {
+            assert_eq!(
+                error_line(parse_obj(source, Path::new("test.obj"))),
+                *line,
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn builds_one_mesh_per_group_and_material() {
+        let default_material = Lambertian::from_rgb(0.5, 0.5, 0.5);
+        let contents = parse(&format!("{}f 1 2 3\ng other\nf 1 3 4\n", SQUARE));
+        let meshes = build_meshes(contents, default_material, Path::new("test.obj")).unwrap();
+        assert_eq!(meshes.objects.len(), 2);
+    }
+
+    #[test]
+    fn rejects_undefined_materials_and_meshes_without_area() {
+        let default_material = Lambertian::from_rgb(0.5, 0.5, 0.5);
+        let contents = parse(&format!("{}f 1 2 3\n\nusemtl missing\nf 1 2 3\n", SQUARE));
+        let result = build_meshes(contents, default_material.clone(), Path::new("test.obj"));
+        assert_eq!(error_line(result), 7);
+
+        let contents = parse("v 0 0 0\nv 1 0 0\nv 2 0 0\nf 1 2 3\n");
+        match build_meshes(contents, default_material, Path::new("test.obj")) {
+            Err(LoadError::Invalid { .. }) => {}
+            _ => panic!("expected a mesh without area to be rejected"),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use crate::*;
+
+// Keyword and arguments of a line, without comments
+pub(crate) fn statement(line: &str) -> Option<(&str, Vec<&str>)> {
+    let line = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+    let mut tokens = line.split_whitespace();
+    let keyword = tokens.next()?;
+    Some((keyword, tokens.collect()))
+}
+
+pub(crate) fn parse_f64(token: &str, path: &Path, line: usize) -> Result<f64, LoadError> {
+    token
+        .parse::<f64>()
+        .map_err(|_| LoadError::parse(path, line, format!("expected a number, found '{}'", token)))
+}
+
+// Parses exactly `count` numbers, or at least `count` when extra ones are allowed
+pub(crate) fn parse_floats(
+    args: &[&str],
+    count: usize,
+    allow_extra: bool,
+    path: &Path,
+    line: usize,
+) -> Result<Vec<f64>, LoadError> {
+    if args.len() < count || (!allow_extra && args.len() > count) {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("expected {} numbers, found {}", count, args.len()),
+        ));
+    }
+    args[..count]
+        .iter()
+        .map(|token| parse_f64(token, path, line))
+        .collect()
+}
+
+pub(crate) fn parse_vec3(args: &[&str], path: &Path, line: usize) -> Result<Vec3, LoadError> {
+    let values = parse_floats(args, 3, true, path, line)?;
+    Ok(Vec3(values[0], values[1], values[2]))
+}
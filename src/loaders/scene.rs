@@ -0,0 +1,1055 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::*;
+
+type ThreadHittable = dyn Hittable + Send + Sync;
+type ThreadMaterial = dyn Material + Send + Sync;
+type ThreadTexture = dyn Texture + Send + Sync;
+
+/// Top level of a TOML scene description
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    #[serde(default = "black")]
+    background: TextureRef,
+    // Put the objects in a BVH instead of a plain list
+    #[serde(default)]
+    bvh: bool,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+fn black() -> TextureRef {
+    TextureRef::Color([0.0, 0.0, 0.0])
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f64; 3],
+    look_at: [f64; 3],
+    #[serde(default = "y_up")]
+    up: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    #[serde(default = "default_focus_distance")]
+    focus_distance: f64,
+    #[serde(default = "default_shutter")]
+    shutter: [f64; 2],
+}
+
+fn y_up() -> [f64; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_focus_distance() -> f64 {
+    10.0
+}
+
+fn default_shutter() -> [f64; 2] {
+    [0.0, 1.0]
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RenderDesc {
+    width: u32,
+    height: u32,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    roulette_depth: u32,
+    seed: u64,
+    sampler: SamplerKind,
+    filter: FilterKind,
+    noise_target: Option<f64>,
+}
+
+impl Default for RenderDesc {
+    fn default() -> Self {
+        let config = Config::default();
+        Self {
+            width: config.image_width,
+            height: config.image_height,
+            samples_per_pixel: config.samples_per_pixel,
+            max_depth: config.max_depth,
+            roulette_depth: config.roulette_depth,
+            seed: config.seed,
+            sampler: config.sampler,
+            filter: config.filter,
+            noise_target: config.noise_target,
+        }
+    }
+}
+
+// A texture by name, a plain color or a texture written out in place
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Name(String),
+    Color([f64; 3]),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f64; 3],
+    },
+    Checker {
+        size: f64,
+        odd: TextureRef,
+        even: TextureRef,
+    },
+    Noise {
+        scale: f64,
+    },
+    Image {
+        file: PathBuf,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Name(String),
+    Inline(Box<MaterialDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: TextureRef },
+    Metal { albedo: [f64; 3], fuzz: f64 },
+    Dielectric { refract_index: f64 },
+    DiffuseLight { emit: TextureRef },
+    Isotropic { albedo: TextureRef },
+}
+
+// A shape and its transforms, written in one table
+#[derive(Deserialize)]
+#[serde(try_from = "toml::value::Table")]
+struct ObjectDesc {
+    shape: ShapeDesc,
+    transform: TransformDesc,
+}
+
+// Keys of TransformDesc, which are taken out of an object's table before the shape is read
+const TRANSFORM_KEYS: [&str; 7] = [
+    "flip_face",
+    "scale",
+    "rotate_x",
+    "rotate_y",
+    "rotate_z",
+    "translate",
+    "keyframes",
+];
+
+// Read separately as serde can't deny unknown fields of a flattened shape
+impl TryFrom<toml::value::Table> for ObjectDesc {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::value::Table) -> Result<Self, Self::Error> {
+        let transform: toml::value::Table = TRANSFORM_KEYS
+            .iter()
+            .filter_map(|key| Some((key.to_string(), table.remove(*key)?)))
+            .collect();
+        Ok(Self {
+            shape: toml::Value::Table(table).try_into()?,
+            transform: toml::Value::Table(transform).try_into()?,
+        })
+    }
+}
+
+// Transforms apply in the order flip_face, scale, rotate_x, rotate_y, rotate_z, translate,
+// keyframes. Use a group for anything else
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformDesc {
+    #[serde(default)]
+    flip_face: bool,
+    scale: Option<[f64; 3]>,
+    rotate_x: Option<f64>,
+    rotate_y: Option<f64>,
+    rotate_z: Option<f64>,
+    translate: Option<[f64; 3]>,
+    #[serde(default)]
+    keyframes: Vec<KeyframeDesc>,
+}
+
+// Scales, rotates around axis, then translates
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    #[serde(default)]
+    translate: [f64; 3],
+    #[serde(default = "y_up")]
+    axis: [f64; 3],
+    #[serde(default)]
+    degrees: f64,
+    #[serde(default = "unit_scale")]
+    scale: [f64; 3],
+}
+
+fn unit_scale() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialRef,
+        // Where the center is at the end of the shutter interval, for motion blur
+        moving_to: Option<[f64; 3]>,
+    },
+    Rectangle {
+        axis: String,
+        x: [f64; 2],
+        y: [f64; 2],
+        z: [f64; 2],
+        material: MaterialRef,
+    },
+    Cube {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: MaterialRef,
+    },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: MaterialRef,
+    },
+    Mesh {
+        file: PathBuf,
+        // Used by faces that have no material in the file
+        material: Option<MaterialRef>,
+    },
+    Medium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        albedo: TextureRef,
+    },
+    Sky {
+        texture: TextureRef,
+    },
+    Group {
+        #[serde(default)]
+        bvh: bool,
+        objects: Vec<ObjectDesc>,
+    },
+}
+
+/// A parsed TOML scene description, ready to be built once the render settings are final
+pub struct SceneDescription {
+    path: PathBuf,
+    scene: SceneFile,
+}
+
+impl SceneDescription {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| LoadError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_source(&source, path)
+    }
+
+    // Files the scene references are looked up next to path
+    fn from_source(source: &str, path: &Path) -> Result<Self, LoadError> {
+        let scene = toml::from_str(source).map_err(|source| LoadError::Toml {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            scene,
+        })
+    }
+
+    /// Render settings from the scene's `[render]` table
+    pub fn config(&self) -> Config {
+        let render = &self.scene.render;
+        Config {
+            image_width: render.width,
+            image_height: render.height,
+            samples_per_pixel: render.samples_per_pixel,
+            max_depth: render.max_depth,
+            roulette_depth: render.roulette_depth,
+            seed: render.seed,
+            sampler: render.sampler,
+            filter: render.filter,
+            noise_target: render.noise_target,
+        }
+    }
+
+    /// Builds the world and a camera matching the image size in config
+    ///
+    /// Files referenced by the scene, such as images and meshes, are relative to the scene file.
+    pub fn build(&self, config: &Config) -> Result<(World, Arc<Camera>), LoadError> {
+        let path = self.path.as_path();
+        let scene = &self.scene;
+        if config.image_width == 0 || config.image_height == 0 {
+            return Err(LoadError::invalid(path, "image size must not be zero"));
+        }
+        if config
+            .image_width
+            .checked_mul(config.image_height)
+            .is_none()
+        {
+            return Err(LoadError::invalid(path, "image has too many pixels"));
+        }
+
+        let camera = &scene.camera;
+        camera.validate(path)?;
+        let camera = Arc::new(Camera::new(
+            (
+                vec3(camera.look_from),
+                vec3(camera.look_at),
+                vec3(camera.up),
+            ),
+            camera.vfov,
+            config.image_width as f64 / config.image_height as f64,
+            camera.aperture,
+            camera.focus_distance,
+            (camera.shutter[0], camera.shutter[1]),
+        ));
+
+        let mut builder = SceneBuilder {
+            path,
+            directory: path.parent().unwrap_or_else(|| Path::new("")),
+            texture_descs: &scene.textures,
+            material_descs: &scene.materials,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            pending_textures: vec![],
+            meshes: HashMap::new(),
+            time: (scene.camera.shutter[0], scene.camera.shutter[1]),
+        };
+
+        let root = builder.group(&scene.objects, scene.bvh)?;
+        let background = builder.texture(&scene.background)?;
+        Ok((World::new(root, background), camera))
+    }
+}
+
+/// Reads a TOML scene description into a world, camera and render settings
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<(World, Arc<Camera>, Config), LoadError> {
+    let description = SceneDescription::open(path)?;
+    let config = description.config();
+    let (world, camera) = description.build(&config)?;
+    Ok((world, camera, config))
+}
+
+fn vec3(v: [f64; 3]) -> Vec3 {
+    Vec3(v[0], v[1], v[2])
+}
+
+// Infinities and NaNs would otherwise end up in bounding boxes and matrices
+fn finite(path: &Path, key: &str, values: &[f64]) -> Result<(), LoadError> {
+    if values.iter().all(|value| value.is_finite()) {
+        Ok(())
+    } else {
+        Err(LoadError::invalid(path, format!("{} must be finite", key)))
+    }
+}
+
+impl CameraDesc {
+    fn validate(&self, path: &Path) -> Result<(), LoadError> {
+        finite(path, "look_from", &self.look_from)?;
+        finite(path, "look_at", &self.look_at)?;
+        finite(path, "up", &self.up)?;
+        if !(self.vfov > 0.0 && self.vfov < 180.0) {
+            return Err(LoadError::invalid(
+                path,
+                "vfov must be between 0 and 180 degrees",
+            ));
+        }
+        if !(self.aperture.is_finite() && self.aperture >= 0.0) {
+            return Err(LoadError::invalid(
+                path,
+                "aperture must be finite and not negative",
+            ));
+        }
+        if !(self.focus_distance.is_finite() && self.focus_distance > 0.0) {
+            return Err(LoadError::invalid(
+                path,
+                "focus_distance must be finite and positive",
+            ));
+        }
+        finite(path, "shutter", &self.shutter)?;
+        // Moving spheres divide by the shutter interval
+        if self.shutter[0] >= self.shutter[1] {
+            return Err(LoadError::invalid(
+                path,
+                "shutter must open before it closes",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Turns descriptions into objects, building each named texture and material once
+struct SceneBuilder<'a> {
+    path: &'a Path,
+    directory: &'a Path,
+    texture_descs: &'a HashMap<String, TextureDesc>,
+    material_descs: &'a HashMap<String, MaterialDesc>,
+    textures: HashMap<String, Arc<ThreadTexture>>,
+    materials: HashMap<String, Arc<ThreadMaterial>>,
+    // Named textures being built, to catch textures that contain themselves
+    pending_textures: Vec<String>,
+    // Meshes by file and material name, so repeating one reuses its BVH
+    meshes: HashMap<(PathBuf, Option<String>), Arc<ThreadHittable>>,
+    time: (f64, f64),
+}
+
+impl SceneBuilder<'_> {
+    fn texture(&mut self, texture: &TextureRef) -> Result<Arc<ThreadTexture>, LoadError> {
+        match texture {
+            TextureRef::Color(color) => Ok(Arc::new(SolidColor(vec3(*color)))),
+            TextureRef::Inline(desc) => self.build_texture(desc),
+            TextureRef::Name(name) => {
+                if let Some(texture) = self.textures.get(name) {
+                    return Ok(texture.clone());
+                }
+                let descs = self.texture_descs;
+                let desc = descs.get(name).ok_or_else(|| {
+                    LoadError::invalid(self.path, format!("texture '{}' is not defined", name))
+                })?;
+                if self.pending_textures.contains(name) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        format!("texture '{}' contains itself", name),
+                    ));
+                }
+                self.pending_textures.push(name.clone());
+                let texture = self.build_texture(desc);
+                self.pending_textures.pop();
+                let texture = texture?;
+                self.textures.insert(name.clone(), texture.clone());
+                Ok(texture)
+            }
+        }
+    }
+
+    fn build_texture(&mut self, desc: &TextureDesc) -> Result<Arc<ThreadTexture>, LoadError> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor(vec3(*color))),
+            TextureDesc::Checker { size, odd, even } => {
+                CheckerTexture::new(*size, self.texture(odd)?, self.texture(even)?)
+            }
+            TextureDesc::Noise { scale } => NoiseTexture::new(*scale),
+            TextureDesc::Image { file } => {
+                let file = self.directory.join(file);
+                ImageTexture::try_from_file(&file)
+                    .map_err(|source| LoadError::Image { path: file, source })?
+            }
+        })
+    }
+
+    fn material(&mut self, material: &MaterialRef) -> Result<Arc<ThreadMaterial>, LoadError> {
+        match material {
+            MaterialRef::Inline(desc) => self.build_material(desc),
+            MaterialRef::Name(name) => {
+                if let Some(material) = self.materials.get(name) {
+                    return Ok(material.clone());
+                }
+                let descs = self.material_descs;
+                let desc = descs.get(name).ok_or_else(|| {
+                    LoadError::invalid(self.path, format!("material '{}' is not defined", name))
+                })?;
+                let material = self.build_material(desc)?;
+                self.materials.insert(name.clone(), material.clone());
+                Ok(material)
+            }
+        }
+    }
+
+    fn build_material(&mut self, desc: &MaterialDesc) -> Result<Arc<ThreadMaterial>, LoadError> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => Lambertian::from_texture(self.texture(albedo)?),
+            MaterialDesc::Metal { albedo, fuzz } => Metal::new(vec3(*albedo), *fuzz),
+            MaterialDesc::Dielectric { refract_index } => Dielectric::new(*refract_index),
+            MaterialDesc::DiffuseLight { emit } => DiffuseLight::from_texture(self.texture(emit)?),
+            MaterialDesc::Isotropic { albedo } => Isotropic::from_texture(self.texture(albedo)?),
+        })
+    }
+
+    fn group(
+        &mut self,
+        objects: &[ObjectDesc],
+        bvh: bool,
+    ) -> Result<Arc<ThreadHittable>, LoadError> {
+        let mut list = HitList::new();
+        for object in objects {
+            list.add(self.object(object)?);
+        }
+        if !bvh {
+            return Ok(Arc::new(list));
+        }
+        Ok(Arc::new(BVH::from_hit_list(list, self.time)))
+    }
+
+    fn object(&mut self, object: &ObjectDesc) -> Result<Arc<ThreadHittable>, LoadError> {
+        let mut hittable = self.shape(&object.shape)?;
+        let object = &object.transform;
+        if object.flip_face {
+            hittable = FlipFace::new(hittable);
+        }
+        let mut transform = None;
+        let mut then = |next: Transform| {
+            transform = Some(transform.map_or(next, |transform: Transform| transform.then(&next)));
+        };
+        if let Some(scale) = object.scale {
+            if !scale.iter().all(|s| s.is_finite() && *s != 0.0) {
+                return Err(LoadError::invalid(
+                    self.path,
+                    "scale must be finite and not zero",
+                ));
+            }
+            then(
+                Transform::scale(vec3(scale)).ok_or_else(|| {
+                    LoadError::invalid(self.path, "scale is too small to be undone")
+                })?,
+            );
+        }
+        if let Some(degrees) = object.rotate_x {
+            finite(self.path, "rotate_x", &[degrees])?;
+            then(Transform::rotate_x(degrees));
+        }
+        if let Some(degrees) = object.rotate_y {
+            finite(self.path, "rotate_y", &[degrees])?;
+            then(Transform::rotate_y(degrees));
+        }
+        if let Some(degrees) = object.rotate_z {
+            finite(self.path, "rotate_z", &[degrees])?;
+            then(Transform::rotate_z(degrees));
+        }
+        if let Some(offset) = object.translate {
+            finite(self.path, "translate", &offset)?;
+            then(Transform::translate(vec3(offset)));
+        }
+        if let Some(transform) = transform {
+            hittable = Instance::new(hittable, transform);
+        }
+        if !object.keyframes.is_empty() {
+            hittable = MotionTransform::new(hittable, self.keyframes(&object.keyframes)?);
+        }
+        Ok(hittable)
+    }
+
+    fn keyframes(&self, keyframes: &[KeyframeDesc]) -> Result<Vec<Keyframe>, LoadError> {
+        let built = keyframes
+            .iter()
+            .map(|keyframe| {
+                finite(self.path, "keyframe time", &[keyframe.time])?;
+                finite(self.path, "keyframe translate", &keyframe.translate)?;
+                finite(self.path, "keyframe axis", &keyframe.axis)?;
+                finite(self.path, "keyframe degrees", &[keyframe.degrees])?;
+                if !keyframe.scale.iter().all(|s| s.is_finite() && *s != 0.0) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        "scale must be finite and not zero",
+                    ));
+                }
+                if keyframe.axis == [0.0, 0.0, 0.0] {
+                    return Err(LoadError::invalid(self.path, "axis must not be zero"));
+                }
+                Ok(Keyframe::new(keyframe.time)
+                    .translate(vec3(keyframe.translate))
+                    .rotate(vec3(keyframe.axis), keyframe.degrees)
+                    .scale(vec3(keyframe.scale)))
+            })
+            .collect::<Result<Vec<Keyframe>, LoadError>>()?;
+        // Only after the times are known not to be NaN, which compares false with everything
+        if keyframes
+            .windows(2)
+            .any(|pair| pair[0].time >= pair[1].time)
+        {
+            return Err(LoadError::invalid(
+                self.path,
+                "keyframe times must be increasing",
+            ));
+        }
+        // Scales are interpolated, so one changing sign would pass through zero
+        if keyframes
+            .windows(2)
+            .any(|pair| (0..3).any(|i| (pair[0].scale[i] > 0.0) != (pair[1].scale[i] > 0.0)))
+        {
+            return Err(LoadError::invalid(
+                self.path,
+                "keyframe scales must not change sign",
+            ));
+        }
+        Ok(built)
+    }
+
+    fn shape(&mut self, shape: &ShapeDesc) -> Result<Arc<ThreadHittable>, LoadError> {
+        Ok(match shape {
+            ShapeDesc::Sphere {
+                center,
+                radius,
+                material,
+                moving_to,
+            } => {
+                finite(self.path, "center", center)?;
+                if let Some(moving_to) = moving_to {
+                    finite(self.path, "moving_to", moving_to)?;
+                }
+                if !(radius.is_finite() && *radius > 0.0) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        "radius must be finite and positive",
+                    ));
+                }
+                Arc::new(Sphere {
+                    center: (vec3(*center), vec3(moving_to.unwrap_or(*center))),
+                    time: self.time,
+                    radius: *radius,
+                    material: self.material(material)?,
+                })
+            }
+            ShapeDesc::Rectangle {
+                axis,
+                x,
+                y,
+                z,
+                material,
+            } => {
+                if !["x", "y", "z", "X", "Y", "Z"].contains(&axis.as_str()) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        format!("'{}' is not an axis, use X, Y or Z", axis),
+                    ));
+                }
+                finite(self.path, "x", x)?;
+                finite(self.path, "y", y)?;
+                finite(self.path, "z", z)?;
+                let axis: Axis = axis.as_str().into();
+                let (hort, vert) = match axis {
+                    Axis::X => (y, z),
+                    Axis::Y => (x, z),
+                    Axis::Z => (x, y),
+                };
+                if hort[0] >= hort[1] || vert[0] >= vert[1] {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        "rectangle extents must go from low to high",
+                    ));
+                }
+                AxisRectangle::new(
+                    axis,
+                    (x[0], x[1]),
+                    (y[0], y[1]),
+                    (z[0], z[1]),
+                    self.material(material)?,
+                )
+            }
+            ShapeDesc::Cube { min, max, material } => {
+                finite(self.path, "min", min)?;
+                finite(self.path, "max", max)?;
+                if (0..3).any(|a| min[a] >= max[a]) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        "cube min must be below max on every axis",
+                    ));
+                }
+                Cube::new(vec3(*min), vec3(*max), self.material(material)?)
+            }
+            ShapeDesc::Triangle {
+                vertices,
+                normals,
+                uvs,
+                material,
+            } => {
+                finite(self.path, "vertices", &vertices.concat())?;
+                if let Some(normals) = normals {
+                    finite(self.path, "normals", &normals.concat())?;
+                }
+                if let Some(uvs) = uvs {
+                    finite(self.path, "uvs", &uvs.concat())?;
+                }
+                Triangle::with_attributes(
+                    [vec3(vertices[0]), vec3(vertices[1]), vec3(vertices[2])],
+                    normals.map(|n| [vec3(n[0]), vec3(n[1]), vec3(n[2])]),
+                    uvs.map(|uv| {
+                        [
+                            (uv[0][0], uv[0][1]),
+                            (uv[1][0], uv[1][1]),
+                            (uv[2][0], uv[2][1]),
+                        ]
+                    }),
+                    self.material(material)?,
+                )
+            }
+            ShapeDesc::Mesh { file, material } => self.mesh(file, material.as_ref())?,
+            ShapeDesc::Medium {
+                boundary,
+                density,
+                albedo,
+            } => {
+                if !(density.is_finite() && *density > 0.0) {
+                    return Err(LoadError::invalid(
+                        self.path,
+                        "density must be finite and positive",
+                    ));
+                }
+                ConstantMedium::new(self.object(boundary)?, self.texture(albedo)?, *density)
+            }
+            ShapeDesc::Sky { texture } => SkySphere::from_texture(self.texture(texture)?),
+            ShapeDesc::Group { bvh, objects } => self.group(objects, *bvh)?,
+        })
+    }
+
+    fn mesh(
+        &mut self,
+        file: &Path,
+        material: Option<&MaterialRef>,
+    ) -> Result<Arc<ThreadHittable>, LoadError> {
+        let key = match material {
+            None => Some((file.to_path_buf(), None)),
+            Some(MaterialRef::Name(name)) => Some((file.to_path_buf(), Some(name.clone()))),
+            // Inline materials are built anew every time, so there is nothing to share
+            Some(MaterialRef::Inline(_)) => None,
+        };
+        if let Some(mesh) = key.as_ref().and_then(|key| self.meshes.get(key)) {
+            return Ok(mesh.clone());
+        }
+
+        let material = match material {
+            Some(material) => self.material(material)?,
+            None => Lambertian::from_rgb(0.8, 0.8, 0.8),
+        };
+        let mut meshes = load_obj(self.directory.join(file), material)?;
+        let mesh: Arc<ThreadHittable> = match meshes.objects.len() {
+            0 => {
+                return Err(LoadError::invalid(
+                    self.path,
+                    format!("mesh {} has no faces", file.display()),
+                ))
+            }
+            1 => meshes.objects.remove(0),
+            _ => Arc::new(BVH::from_hit_list(meshes, self.time)),
+        };
+        if let Some(key) = key {
+            self.meshes.insert(key, mesh.clone());
+        }
+        Ok(mesh)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "[camera]\nlook_from = [0, 0, 5]\nlook_at = [0, 0, 0]\nvfov = 40\n";
+
+    // Top level keys have to come first in scene, as the camera is added after it
+    fn open(scene: &str) -> Result<SceneDescription, LoadError> {
+        SceneDescription::from_source(&format!("{}\n{}", scene, CAMERA), Path::new("test.toml"))
+    }
+
+    fn build(scene: &str) -> Result<(World, Arc<Camera>), LoadError> {
+        let description = open(scene)?;
+        description.build(&description.config())
+    }
+
+    fn error_message(scene: &str) -> String {
+        match build(scene) {
+            Ok(_) => panic!("expected an error for {:?}", scene),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn builds_a_minimal_scene() {
+        let scene = r#"
+            [render]
+            width = 40
+            height = 30
+
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.7, 0.7, 0.7]
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "white"
+            translate = [0, 1, 0]
+            "#;
+        let config = open(scene).unwrap().config();
+        assert_eq!((config.image_width, config.image_height), (40, 30));
+        assert_eq!(
+            config.samples_per_pixel,
+            Config::default().samples_per_pixel
+        );
+        build(scene).unwrap();
+    }
+
+    #[test]
+    fn rejects_images_with_too_many_pixels() {
+        let message = error_message("[render]\nwidth = 70000\nheight = 70000\n");
+        assert!(message.contains("too many pixels"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+                      material = { type = \"dielectric\", refract_index = 1.5 }\n";
+        for (key, value) in [
+            ("rotate-y", "30"),
+            ("flipface", "true"),
+            ("scael", "[1, 1, 1]"),
+            ("radius2", "1"),
+        ]
+        .iter()
+        {
+            let message = error_message(&format!("{}{} = {}\n", sphere, key, value));
+            assert!(message.contains(key), "{}", message);
+        }
+        let message = error_message("[render]\nsamples = 4\n");
+        assert!(message.contains("samples"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_numbers_that_are_not_finite() {
+        let material = "material = { type = \"dielectric\", refract_index = 1.5 }";
+        let sphere = |center: &str, radius: &str| {
+            format!(
+                "[[objects]]\ntype = \"sphere\"\ncenter = {}\nradius = {}\n{}\n",
+                center, radius, material
+            )
+        };
+        let unit_sphere = sphere("[0, 0, 0]", "1");
+        for (scene, error) in [
+            (format!("{}rotate_x = nan\n", unit_sphere), "rotate_x"),
+            (format!("{}rotate_y = nan\n", unit_sphere), "rotate_y"),
+            (format!("{}rotate_z = inf\n", unit_sphere), "rotate_z"),
+            (
+                format!("{}translate = [0, -inf, 0]\n", unit_sphere),
+                "translate",
+            ),
+            (sphere("[nan, 0, 0]", "1"), "center"),
+            (
+                format!("{}moving_to = [0, inf, 0]\n", unit_sphere),
+                "moving_to",
+            ),
+            (sphere("[0, 0, 0]", "inf"), "radius"),
+            (sphere("[0, 0, 0]", "nan"), "radius"),
+            (sphere("[0, 0, 0]", "0"), "radius"),
+            (sphere("[0, 0, 0]", "-1"), "radius"),
+            (
+                format!("bvh = true\n{}", sphere("[0, 0, 0]", "inf")),
+                "radius",
+            ),
+            (
+                format!(
+                    "[[objects]]\ntype = \"rectangle\"\naxis = \"y\"\nx = [0, 1]\n\
+                     y = [nan, nan]\nz = [0, 1]\n{}\n",
+                    material
+                ),
+                "y must be finite",
+            ),
+            (
+                format!(
+                    "[[objects]]\ntype = \"cube\"\nmin = [0, 0, 0]\nmax = [1, inf, 1]\n{}\n",
+                    material
+                ),
+                "max must be finite",
+            ),
+            (
+                format!(
+                    "[[objects]]\ntype = \"cube\"\nmin = [0, 0, 0]\nmax = [1, -1, 1]\n{}\n",
+                    material
+                ),
+                "below max",
+            ),
+            (
+                format!(
+                    "[[objects]]\ntype = \"triangle\"\n\
+                     vertices = [[0, 0, 0], [1, 0, 0], [0, nan, 0]]\n{}\n",
+                    material
+                ),
+                "vertices",
+            ),
+            (
+                format!(
+                    "[[objects]]\ntype = \"medium\"\ndensity = inf\nalbedo = [1, 1, 1]\n\
+                     boundary = {{ type = \"sphere\", center = [0, 0, 0], radius = 1, \
+                     {} }}\n",
+                    material
+                ),
+                "density",
+            ),
+        ]
+        .iter()
+        {
+            let message = error_message(scene);
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+
+    #[test]
+    fn rejects_scales_that_cannot_be_undone() {
+        let message = error_message(
+            "[[objects]]\ntype = \"cube\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\n\
+             material = { type = \"dielectric\", refract_index = 1.5 }\nscale = [1e-13, 1, 1]\n",
+        );
+        assert!(message.contains("scale is too small"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_bad_cameras() {
+        let camera = |fields: &str| {
+            let source = format!("[camera]\n{}\n", fields);
+            let description = SceneDescription::from_source(&source, Path::new("test.toml"))?;
+            description.build(&description.config())
+        };
+        let view = "look_from = [0, 0, 5]\nlook_at = [0, 0, 0]\n";
+        camera(&format!("{}vfov = 40\nshutter = [0.5, 2]", view)).unwrap();
+        for (fields, error) in [
+            (
+                "look_from = [0, 0, inf]\nlook_at = [0, 0, 0]\nvfov = 40".to_string(),
+                "look_from",
+            ),
+            (
+                "look_from = [0, 0, 5]\nlook_at = [nan, 0, 0]\nvfov = 40".to_string(),
+                "look_at",
+            ),
+            (format!("{}vfov = 40\nup = [0, nan, 0]", view), "up"),
+            (format!("{}vfov = nan", view), "vfov"),
+            (format!("{}vfov = 180", view), "vfov"),
+            (format!("{}vfov = 40\naperture = inf", view), "aperture"),
+            (format!("{}vfov = 40\naperture = -1", view), "aperture"),
+            (
+                format!("{}vfov = 40\nfocus_distance = 0", view),
+                "focus_distance",
+            ),
+            (
+                format!("{}vfov = 40\nfocus_distance = nan", view),
+                "focus_distance",
+            ),
+            (
+                format!("{}vfov = 40\nshutter = [0, inf]", view),
+                "shutter must be finite",
+            ),
+            (
+                format!("{}vfov = 40\nshutter = [1, 1]", view),
+                "open before it closes",
+            ),
+        ]
+        .iter()
+        {
+            let message = match camera(fields) {
+                Ok(_) => panic!("expected an error for {:?}", fields),
+                Err(err) => err.to_string(),
+            };
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+
+    #[test]
+    fn rejects_keyframe_scales_through_zero() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+                      material = { type = \"dielectric\", refract_index = 1.5 }\n";
+        let keyframes = |first: &str, second: &str| {
+            format!(
+                "{}keyframes = [{{ time = 0, scale = {} }}, {{ time = 1, scale = {} }}]\n",
+                sphere, first, second
+            )
+        };
+        build(&keyframes("[1, 1, 1]", "[2, 0.5, 3]")).unwrap();
+        build(&keyframes("[-1, 1, 1]", "[-2, 1, 1]")).unwrap();
+        for (first, second, error) in [
+            ("[1, 1, 1]", "[-1, 1, 1]", "change sign"),
+            ("[1, -1, 1]", "[1, 1, 1]", "change sign"),
+            ("[1, 1, 1]", "[1, 0, 1]", "not zero"),
+            ("[1, 1, nan]", "[1, 1, 1]", "finite"),
+            ("[1, 1, 1]", "[inf, 1, 1]", "finite"),
+        ]
+        .iter()
+        {
+            let message = error_message(&keyframes(first, second));
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+
+    #[test]
+    fn rejects_keyframes_out_of_order_or_not_finite() {
+        let sphere = "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+                      material = { type = \"dielectric\", refract_index = 1.5 }\n";
+        let keyframes = |keyframes: &str| format!("{}keyframes = [{}]\n", sphere, keyframes);
+        build(&keyframes("{ time = 0 }, { time = 0.5, degrees = 90 }")).unwrap();
+        for (scene, error) in [
+            ("{ time = 1 }, { time = 0 }", "increasing"),
+            ("{ time = 0 }, { time = 0 }", "increasing"),
+            ("{ time = nan }, { time = 1 }", "time must be finite"),
+            ("{ time = 0 }, { time = nan }", "time must be finite"),
+            ("{ time = nan }", "time must be finite"),
+            ("{ time = 0 }, { time = inf }", "time must be finite"),
+            (
+                "{ time = 0, translate = [0, nan, 0] }",
+                "translate must be finite",
+            ),
+            ("{ time = 0, axis = [inf, 0, 0] }", "axis must be finite"),
+            ("{ time = 0, degrees = nan }", "degrees must be finite"),
+        ]
+        .iter()
+        {
+            let message = error_message(&keyframes(scene));
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+
+    #[test]
+    fn rejects_missing_materials() {
+        let message = error_message(
+            "[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"chrome\"\n",
+        );
+        assert!(
+            message.contains("material 'chrome' is not defined"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn rejects_bad_references() {
+        let message = error_message(
+            "[materials.floor]\ntype = \"lambertian\"\nalbedo = \"tiles\"\n\
+             [[objects]]\ntype = \"cube\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\nmaterial = \"floor\"\n",
+        );
+        assert!(
+            message.contains("texture 'tiles' is not defined"),
+            "{}",
+            message
+        );
+
+        let message = error_message(
+            "background = \"loop\"\n[textures.loop]\ntype = \"checker\"\nsize = 1\n\
+             odd = \"loop\"\neven = [0, 0, 0]\n",
+        );
+        assert!(message.contains("contains itself"), "{}", message);
+    }
+}
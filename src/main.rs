@@ -1,125 +1,207 @@
-use std::sync::Arc;
-use std::time::SystemTime;
-use std::time::{Duration, Instant};
+use std::path::Path;
+use std::time::Instant;
 
-use chrono::offset::Utc;
-use chrono::DateTime;
-use image::*;
-use rand::random;
-use rand::Rng;
+use pathtracer::*;
 
-mod acceleration;
-mod camera;
-mod geometry;
-mod material;
-mod onb;
-mod perlin;
-mod ray;
-mod scenes;
-mod texture;
-mod util;
-mod vec3;
-mod volume;
-mod worker;
-mod world;
-
-pub use acceleration::*;
-pub use camera::*;
-pub use geometry::*;
-pub use material::*;
-pub use onb::*;
-pub use perlin::*;
-pub use ray::*;
-pub use scenes::*;
-pub use texture::*;
-pub use util::*;
-pub use vec3::*;
-pub use volume::*;
-pub use worker::*;
-pub use world::*;
+mod cli;
 
 fn main() {
-    let quality = 5;
-    let config = Config {
-        image_width: 100 * quality,
-        image_height: 100 * quality,
-        samples_per_pixel: 10,
-        max_depth: 50,
-    };
+    let options = cli::Options::from_args();
+    if let Some(directory) = options.output.parent() {
+        if let Err(err) = std::fs::create_dir_all(directory) {
+            eprintln!("Could not create {}: {}", directory.display(), err);
+            std::process::exit(1);
+        }
+    }
+    if !options.merge.is_empty() {
+        merge(&options);
+        return;
+    }
 
-    let mut image_buffer = DynamicImage::new_rgb8(config.image_width, config.image_height).to_rgb();
+    // World generation, with the settings of the checkpoint when resuming
+    let (scene, resume) = match &options.resume {
+        Some(path) => {
+            let checkpoint = load_checkpoint(path);
+            if let Some(scene) = &options.scene {
+                if *scene != checkpoint.scene {
+                    eprintln!(
+                        "{} is a checkpoint of {}, not {}",
+                        path.display(),
+                        checkpoint.scene,
+                        scene
+                    );
+                    std::process::exit(1);
+                }
+            }
+            (checkpoint.scene.clone(), Some(checkpoint))
+        }
+        None => (options.scene_name().to_string(), None),
+    };
+    let loaded = match &resume {
+        Some(checkpoint) => cli::load_scene_with_config(&scene, &checkpoint.config)
+            .map(|(world, camera)| (world, camera, checkpoint.config.clone())),
+        None => options.load_scene(),
+    };
+    let (world, camera, config) = match loaded {
+        Ok(scene) => scene,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    for stats in world.bvh_stats() {
+        println!("{}", stats);
+    }
 
-    // World generation
-    let (world, camera) = cornell_box(&config);
+    // The first interrupt stops the render once the tiles under way are done and saves them,
+    // the second quits at once
+    let cancellation = CancellationToken::new();
+    let interrupt = cancellation.clone();
+    let handler = ctrlc::set_handler(move || {
+        if interrupt.is_cancelled() {
+            eprintln!("\nQuitting");
+            std::process::exit(130);
+        }
+        eprintln!("\nInterrupted, finishing the tiles under way. Interrupt again to quit now.");
+        interrupt.cancel();
+    });
+    if let Err(err) = handler {
+        eprintln!("Could not handle interrupts: {}", err);
+    }
 
-    let worker_pool = WorkerPool::spawn(12, world, camera, config.clone());
-    let worker_pool = Arc::new(worker_pool);
+    // Checkpoints written keep the seeds merged into the one resumed
+    let seeds = match &resume {
+        Some(checkpoint) => checkpoint.seeds.clone(),
+        None => vec![config.seed],
+    };
+    let mut renderer = Renderer::new(world, camera, config)
+        .threads(options.threads)
+        .cancellation(cancellation.clone());
+    if let Some(checkpoint) = resume {
+        println!(
+            "Resuming from {} of {} samples per pixel",
+            checkpoint.samples_done, checkpoint.config.samples_per_pixel
+        );
+        if checkpoint.samples_done == checkpoint.config.samples_per_pixel {
+            save_images(&options, &checkpoint.config, &checkpoint.framebuffer);
+            return;
+        }
+        renderer = renderer
+            .resume(
+                checkpoint.framebuffer,
+                checkpoint.samples_done,
+                checkpoint.tiles_done,
+            )
+            .unwrap_or_else(|err| {
+                eprintln!("Could not resume: {}", err);
+                std::process::exit(1);
+            });
+    }
+    // Resumed renders keep their checkpoint up to date
+    let checkpoint_output = options.checkpoint.as_ref().or(options.resume.as_ref());
     let render_start = Instant::now();
 
-    let ramp = sampling_ramp(config.samples_per_pixel);
+    let mut pass_start = Instant::now();
+    let mut progress_bar = progress::Bar::new();
+    let mut current_pass = 0;
+    let (_, stats) = renderer.render_with(|progress| {
+        if progress.pass != current_pass {
+            current_pass = progress.pass;
+            pass_start = Instant::now();
+            progress_bar = progress::Bar::new();
+            progress_bar.set_job_title(&format!(
+                "Rendering Pass {}/{}",
+                progress.pass, progress.passes
+            ));
+        }
+        progress_bar
+            .reach_percent((progress.pixels_done as u64 * 100 / progress.pixels as u64) as i32);
 
-    {
-        let worker_pool = worker_pool.clone();
-        let config = config.clone();
-        let ramp = ramp.clone();
-        std::thread::spawn(move || {
-            for s in ramp.iter() {
-                for v in 0..config.image_height {
-                    for u in 0..config.image_width {
-                        worker_pool.send_job(*s, u, v);
-                    }
-                }
+        if progress.pass_done() {
+            if cancellation.is_cancelled() {
+                println!("\nPass {} interrupted\n", progress.pass);
+            } else {
+                println!(
+                    "\nPass {} took {} seconds\n",
+                    progress.pass,
+                    pass_start.elapsed().as_secs()
+                );
             }
-        });
-    }
+            save_images(&options, renderer.config(), progress.framebuffer);
+            if let Some(path) = checkpoint_output {
+                let checkpoint = Checkpoint {
+                    scene: scene.clone(),
+                    config: renderer.config().clone(),
+                    seeds: seeds.clone(),
+                    samples_done: progress.samples_done,
+                    tiles_done: progress.tiles_done.to_vec(),
+                    framebuffer: progress.framebuffer.clone(),
+                };
+                save_checkpoint(&checkpoint, path);
+            }
+        }
+    });
 
-    let system_time = SystemTime::now();
-    let datetime: DateTime<Utc> = system_time.into();
+    println!("Render took {} seconds", render_start.elapsed().as_secs());
+    println!("Traced {}", stats);
+    if cancellation.is_cancelled() {
+        if let Some(path) = checkpoint_output {
+            println!("Continue with --resume {}", path.display());
+        }
+    }
+}
 
-    let mut color_weights =
-        vec![(0, Vec3::zero()); (config.image_height * config.image_width) as usize];
-    for pass in 1..=ramp.len() {
-        let pass_start = Instant::now();
-        let mut progress_bar = progress::Bar::new();
-        progress_bar.set_job_title(&format!("Rendering Pass {}/{}", pass, ramp.len()));
-        for prog in 0..(config.image_height * config.image_width) {
-            let (new_weight, x, y, new_color) = worker_pool.recv_color();
-            let cur_color_weight = &mut color_weights[(y * config.image_width + x) as usize];
-            *cur_color_weight = (
-                new_weight + cur_color_weight.0,
-                new_color + cur_color_weight.1,
-            );
-            let color = cur_color_weight.1 / cur_color_weight.0 as f64;
+// Combines the checkpoints given to --merge instead of rendering
+fn merge(options: &cli::Options) {
+    let mut checkpoints = options.merge.iter().map(|path| load_checkpoint(path));
+    let first = checkpoints.next().unwrap();
+    let merged = checkpoints.try_fold(first, |merged, checkpoint| merged.merge(&checkpoint));
+    let merged = match merged {
+        Ok(merged) => merged,
+        Err(err) => {
+            eprintln!("Could not merge: {}", err);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Merged {} checkpoints of {} into {} samples per pixel",
+        options.merge.len(),
+        merged.scene,
+        merged.samples_done
+    );
+    save_images(options, &merged.config, &merged.framebuffer);
+    if let Some(path) = &options.checkpoint {
+        save_checkpoint(&merged, path);
+    }
+}
 
-            image_buffer.put_pixel(x, config.image_height - 1 - y, color.into());
-            let progress = (prog * 100) / (config.image_height * config.image_width - 1);
-            progress_bar.reach_percent(progress as i32);
+// Writes the image, and the sample heatmap of adaptive renders
+fn save_images(options: &cli::Options, config: &Config, framebuffer: &Framebuffer) {
+    let image = framebuffer.to_rgb_image();
+    if let Err(err) = image.save_with_format(&options.output, options.format) {
+        eprintln!("Could not save {}: {}", options.output.display(), err);
+        std::process::exit(1);
+    }
+    if config.noise_target.is_some() {
+        let heatmap_output = options.heatmap_output();
+        let heatmap = framebuffer.sample_heatmap();
+        if let Err(err) = heatmap.save_with_format(&heatmap_output, options.format) {
+            eprintln!("Could not save {}: {}", heatmap_output.display(), err);
+            std::process::exit(1);
         }
-        println!(
-            "\nPass {} took {} seconds\n",
-            pass,
-            pass_start.elapsed().as_secs()
-        );
-        image_buffer
-            .save(format!(
-                "output/{}_{}.png",
-                datetime.format("%Y_%m_%d_%H_%M_%S"),
-                pass
-            ))
-            .unwrap();
     }
+}
 
-    println!("Render took {} seconds", render_start.elapsed().as_secs());
+fn load_checkpoint(path: &Path) -> Checkpoint {
+    Checkpoint::load(path).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
 }
 
-fn sampling_ramp(mut total_samples: u32) -> Vec<u32> {
-    let mut sampling_ramp = vec![];
-    let mut cur: u32;
-    while total_samples > 64 {
-        cur = total_samples / 2;
-        total_samples -= cur;
-        sampling_ramp.push(cur);
+fn save_checkpoint(checkpoint: &Checkpoint, path: &Path) {
+    if let Err(err) = checkpoint.save(path) {
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
-    sampling_ramp.push(total_samples);
-    sampling_ramp.into_iter().rev().collect()
 }
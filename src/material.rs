@@ -1,22 +1,63 @@
 use std::f64::consts::PI;
 use std::sync::Arc;
 
-use rand::random;
+use rand::Rng;
 
 use crate::*;
 
+pub struct ScatterRecord {
+    pub attenuation: Color3,
+    // Specular bounces follow this ray and skip light sampling
+    pub specular_ray: Option<Ray>,
+    pub pdf: Option<Box<dyn Pdf>>,
+}
+
+impl ScatterRecord {
+    pub fn specular(attenuation: Color3, specular_ray: Ray) -> Self {
+        Self {
+            attenuation,
+            specular_ray: Some(specular_ray),
+            pdf: None,
+        }
+    }
+
+    pub fn diffuse(attenuation: Color3, pdf: Box<dyn Pdf>) -> Self {
+        Self {
+            attenuation,
+            specular_ray: None,
+            pdf: Some(pdf),
+        }
+    }
+}
+
 pub trait Material {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray, f64)> {
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: &HitRecord,
+        _rng: &mut SampleRng,
+    ) -> Option<ScatterRecord> {
         None
     }
 
-    fn scattering_pdf(&self, ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+    fn scattering_pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _scattered: &Ray) -> f64 {
         1.0
     }
 
-    fn emitted(&self, ray_in: &Ray, hit_record: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+    fn emitted(
+        &self,
+        _ray_in: &Ray,
+        _hit_record: &HitRecord,
+        _u: f64,
+        _v: f64,
+        _p: &Point3,
+    ) -> Color3 {
         Vec3::zero()
     }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -42,17 +83,20 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray_in: &Ray, hit: &HitRecord) -> Option<(Vec3, Ray, f64)> {
-        let uvw = ONB::build_from_w(&hit.normal);
-        //let scatter_direction = Vec3::random_in_hemisphere(hit.normal);
-        let scatter_direction = uvw.local(&Vec3::random_cosine_direction());
-        let scatter_ray = Ray::new(hit.position, scatter_direction, ray_in.time);
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        hit: &HitRecord,
+        _rng: &mut SampleRng,
+    ) -> Option<ScatterRecord> {
         let albedo = self.albedo.value(hit.u, hit.v, &hit.position);
-        let pdf = uvw.w().dot(scatter_ray.direction) / PI;
-        Some((albedo, scatter_ray, pdf))
+        Some(ScatterRecord::diffuse(
+            albedo,
+            Box::new(CosinePdf::new(&hit.normal)),
+        ))
     }
 
-    fn scattering_pdf(&self, ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+    fn scattering_pdf(&self, _ray_in: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
         let cosine = hit_record.normal.dot(scattered.direction.unit_vector());
         if cosine < 0.0 {
             0.0
@@ -77,16 +121,20 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray, f64)> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut SampleRng,
+    ) -> Option<ScatterRecord> {
         let reflected = ray_in.direction.reflect(hit_record.normal);
         let scatter_ray = Ray::new(
             hit_record.position,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
+            reflected + self.fuzz * Vec3::random_in_unit_sphere(rng),
             ray_in.time,
         );
-        let attenuation = self.albedo;
         if scatter_ray.direction.dot(hit_record.normal) > 0.0 {
-            Some((attenuation, scatter_ray, 1.0))
+            Some(ScatterRecord::specular(self.albedo, scatter_ray))
         } else {
             None
         }
@@ -112,7 +160,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray, f64)> {
+    fn scatter(
+        &self,
+        ray_in: &Ray,
+        hit_record: &HitRecord,
+        rng: &mut SampleRng,
+    ) -> Option<ScatterRecord> {
         let attenuation = Vec3(1.0, 1.0, 1.0);
         let etai_over_etat = if hit_record.front_face {
             1.0 / self.refract_index
@@ -124,16 +177,15 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let reflect_prob = self.schlick(cos_theta);
         let scatter_direction =
-            if etai_over_etat * sin_theta > 1.0 || random::<f64>() < reflect_prob {
+            if etai_over_etat * sin_theta > 1.0 || rng.gen::<f64>() < reflect_prob {
                 ray_in.direction.reflect(hit_record.normal)
             } else {
                 ray_in.direction.refract(hit_record.normal, etai_over_etat)
             };
 
-        Some((
+        Some(ScatterRecord::specular(
             attenuation,
             Ray::new(hit_record.position, scatter_direction, ray_in.time),
-            1.0,
         ))
     }
 }
@@ -149,13 +201,17 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn emitted(&self, ray_in: &Ray, hit_record: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
+    fn emitted(&self, _ray_in: &Ray, hit_record: &HitRecord, u: f64, v: f64, p: &Point3) -> Color3 {
         if hit_record.front_face {
             self.emit.value(u, v, p)
         } else {
             Vec3::zero()
         }
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
 pub struct Isotropic {
@@ -169,15 +225,19 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, ray_in: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray, f64)> {
-        let scattered = Ray::new(
-            hit_record.position,
-            Vec3::random_in_unit_sphere(),
-            ray_in.time,
-        );
+    fn scatter(
+        &self,
+        _ray_in: &Ray,
+        hit_record: &HitRecord,
+        _rng: &mut SampleRng,
+    ) -> Option<ScatterRecord> {
         let attenuation = self
             .albedo
             .value(hit_record.u, hit_record.v, &hit_record.position);
-        Some((attenuation, scattered, 1.0))
+        Some(ScatterRecord::diffuse(attenuation, Box::new(SpherePdf)))
+    }
+
+    fn scattering_pdf(&self, _ray_in: &Ray, _hit_record: &HitRecord, _scattered: &Ray) -> f64 {
+        0.25 / PI
     }
 }
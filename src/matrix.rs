@@ -0,0 +1,516 @@
+use std::ops;
+
+use crate::*;
+
+/// Row major 4x4 matrix, acting on column vectors
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct Matrix4(pub [[f64; 4]; 4]);
+
+impl Matrix4 {
+    pub fn identity() -> Self {
+        Self::from_diagonal(1.0, 1.0, 1.0)
+    }
+
+    fn from_diagonal(x: f64, y: f64, z: f64) -> Self {
+        Self([
+            [x, 0.0, 0.0, 0.0],
+            [0.0, y, 0.0, 0.0],
+            [0.0, 0.0, z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        for a in 0..3 {
+            matrix.0[a][3] = offset[a];
+        }
+        matrix
+    }
+
+    pub fn scaling(scale: Vec3) -> Self {
+        Self::from_diagonal(scale.x(), scale.y(), scale.z())
+    }
+
+    /// Counter clockwise rotation about axis, looking down the axis towards the origin
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let Vec3(x, y, z) = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Self([
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.0,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.0,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut transpose = Self::identity();
+        for (r, row) in self.0.iter().enumerate() {
+            for (c, value) in row.iter().enumerate() {
+                transpose.0[c][r] = *value;
+            }
+        }
+        transpose
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    ///
+    /// Matrices with infinite or NaN entries count as singular.
+    pub fn inverse(&self) -> Option<Self> {
+        if self.0.iter().flatten().any(|value| !value.is_finite()) {
+            return None;
+        }
+        let mut m = self.0;
+        let mut inverse = Self::identity().0;
+        for c in 0..4 {
+            let pivot = (c..4)
+                .max_by(|a, b| m[*a][c].abs().total_cmp(&m[*b][c].abs()))
+                .unwrap();
+            if m[pivot][c].abs() < 1e-12 {
+                return None;
+            }
+            m.swap(c, pivot);
+            inverse.swap(c, pivot);
+
+            let scale = 1.0 / m[c][c];
+            for k in 0..4 {
+                m[c][k] *= scale;
+                inverse[c][k] *= scale;
+            }
+            for r in 0..4 {
+                if r != c {
+                    let factor = m[r][c];
+                    for k in 0..4 {
+                        m[r][k] -= factor * m[c][k];
+                        inverse[r][k] -= factor * inverse[c][k];
+                    }
+                }
+            }
+        }
+        Some(Self(inverse))
+    }
+
+    /// Determinant of the upper left 3x3 part, which is all that deforms an affine transform
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * p.0 + m[0][1] * p.1 + m[0][2] * p.2 + m[0][3],
+            m[1][0] * p.0 + m[1][1] * p.1 + m[1][2] * p.2 + m[1][3],
+            m[2][0] * p.0 + m[2][1] * p.1 + m[2][2] * p.2 + m[2][3],
+        )
+    }
+
+    // Ignores translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+}
+
+impl ops::Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Self) -> Self::Output {
+        let mut product = [[0.0; 4]; 4];
+        for (r, row) in product.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[r][k] * other.0[k][c]).sum();
+            }
+        }
+        Self(product)
+    }
+}
+
+/// Invertible affine transform from object space to world space
+#[derive(Copy, Debug, Clone, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    /// None if matrix can't be inverted
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Self { matrix, inverse })
+    }
+
+    pub fn identity() -> Self {
+        Self {
+            matrix: Matrix4::identity(),
+            inverse: Matrix4::identity(),
+        }
+    }
+
+    /// Scales, then rotates, then translates, without having to invert a matrix
+    pub fn from_parts(translation: Vec3, rotation: Quaternion, scale: Vec3) -> Self {
+        assert!(scale.x() != 0.0 && scale.y() != 0.0 && scale.z() != 0.0);
+        let rotation = rotation.to_matrix();
+        Self {
+            matrix: Matrix4::translation(translation) * rotation * Matrix4::scaling(scale),
+            inverse: Matrix4::scaling(Vec3(1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z()))
+                * rotation.transpose()
+                * Matrix4::translation(-translation),
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        Self {
+            matrix: Matrix4::translation(offset),
+            inverse: Matrix4::translation(-offset),
+        }
+    }
+
+    /// None if a side is scaled to nothing
+    pub fn scale(scale: Vec3) -> Option<Self> {
+        Self::new(Matrix4::scaling(scale))
+    }
+
+    // The inverse of a rotation is its transpose
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let matrix = Matrix4::rotation(axis, degrees);
+        Self {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Self::rotate(Vec3(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Self::rotate(Vec3(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Self::rotate(Vec3(0.0, 0.0, 1.0), degrees)
+    }
+
+    /// This transform followed by other
+    pub fn then(&self, other: &Transform) -> Self {
+        Self {
+            matrix: other.matrix * self.matrix,
+            inverse: self.inverse * other.inverse,
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // Normals stay perpendicular to surfaces under the inverse transpose
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n).unit_vector()
+    }
+
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new(self.point(r.origin), self.vector(r.direction), r.time)
+    }
+
+    /// Smallest box around the transformed box (Arvo's method)
+    pub fn bounding_box(&self, bbox: &AABB) -> AABB {
+        let m = &self.matrix.0;
+        let mut min = Vec3::zero();
+        let mut max = Vec3::zero();
+        for (r, row) in m.iter().take(3).enumerate() {
+            min[r] = row[3];
+            max[r] = row[3];
+            for (c, value) in row.iter().take(3).enumerate() {
+                let a = value * bbox.min[c];
+                let b = value * bbox.max[c];
+                min[r] += a.min(b);
+                max[r] += a.max(b);
+            }
+        }
+        AABB::new(min, max)
+    }
+}
+
+/// Unit quaternion describing a rotation
+#[derive(Copy, Debug, Clone)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quaternion {
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            v: Vec3::zero(),
+        }
+    }
+
+    /// Same rotation as `Matrix4::rotation`
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Self {
+            w: cos,
+            v: axis.unit_vector() * sin,
+        }
+    }
+
+    pub fn dot(&self, other: &Self) -> f64 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    /// Angle in radians of the rotation taking self to other
+    pub fn angle_to(&self, other: &Self) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    /// Rotation at constant angular speed from self at t = 0 to other at t = 1
+    pub fn slerp(&self, other: &Self, t: f64) -> Self {
+        let mut cos_theta = self.dot(other);
+        // q and -q are the same rotation, take the short way around
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Self {
+                w: -other.w,
+                v: -other.v,
+            }
+        } else {
+            *other
+        };
+
+        let (a, b) = if cos_theta > 0.9995 {
+            // Nearly parallel, where a lerp is accurate and sin_theta would be unstable
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Self {
+            w: a * self.w + b * other.w,
+            v: self.v * a + other.v * b,
+        }
+        .normalized()
+    }
+
+    fn normalized(&self) -> Self {
+        let length = self.dot(self).sqrt();
+        Self {
+            w: self.w / length,
+            v: self.v / length,
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let Self {
+            w,
+            v: Vec3(x, y, z),
+        } = *self;
+        Matrix4([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix4, b: &Matrix4) {
+        for (row_a, row_b) in a.0.iter().zip(&b.0) {
+            for (value_a, value_b) in row_a.iter().zip(row_b) {
+                assert!((value_a - value_b).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn assert_vec_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let affine = Matrix4::translation(Vec3(1.0, -2.0, 3.0))
+            * Matrix4::rotation(Vec3(1.0, 2.0, 3.0), 40.0)
+            * Matrix4::scaling(Vec3(2.0, 0.5, -3.0));
+        // Needs rows swapped to pivot
+        let general = Matrix4([
+            [0.0, 2.0, 1.0, 4.0],
+            [3.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0, 2.0],
+            [0.0, 1.0, 5.0, 1.0],
+        ]);
+        for m in [affine, general].iter() {
+            let inverse = m.inverse().unwrap();
+            assert_close(&(*m * inverse), &Matrix4::identity());
+            assert_close(&(inverse * *m), &Matrix4::identity());
+        }
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let mut repeated_row = Matrix4::rotation(Vec3(0.0, 1.0, 0.0), 30.0);
+        repeated_row.0[2] = repeated_row.0[0];
+        let mut nan = Matrix4::identity();
+        nan.0[1][2] = f64::NAN;
+        let mut infinite = Matrix4::identity();
+        infinite.0[0][3] = f64::INFINITY;
+        for m in [
+            Matrix4::scaling(Vec3(1.0, 0.0, 1.0)),
+            repeated_row,
+            nan,
+            infinite,
+        ]
+        .iter()
+        {
+            assert!(m.inverse().is_none(), "{:?}", m);
+            assert!(Transform::new(*m).is_none());
+        }
+        assert!(Transform::scale(Vec3(2.0, 2.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = Transform::scale(Vec3(4.0, 0.5, 1.0))
+            .unwrap()
+            .then(&Transform::rotate_z(30.0));
+        // Transformed like a vector, the normal would no longer be perpendicular
+        let tangent = Vec3(1.0, 1.0, 0.0);
+        let normal = Vec3(-1.0, 1.0, 0.0).unit_vector();
+        let transformed = transform.normal(normal);
+        assert!(transform.vector(tangent).dot(transformed).abs() < 1e-9);
+        assert!((transformed.length() - 1.0).abs() < 1e-9);
+        assert!(
+            transform
+                .vector(normal)
+                .dot(transform.vector(tangent))
+                .abs()
+                > 0.1
+        );
+    }
+
+    #[test]
+    fn bounding_box_of_a_rotated_cube() {
+        let cube = AABB::new(Vec3(-1.0, -1.0, -1.0), Vec3(1.0, 1.0, 1.0));
+        let transform = Transform::rotate_y(45.0).then(&Transform::translate(Vec3(0.0, 5.0, 0.0)));
+        let bbox = transform.bounding_box(&cube);
+        let half_diagonal = 2.0f64.sqrt();
+        assert_vec_close(bbox.min, Vec3(-half_diagonal, 4.0, -half_diagonal));
+        assert_vec_close(bbox.max, Vec3(half_diagonal, 6.0, half_diagonal));
+    }
+
+    #[test]
+    fn then_applies_self_first() {
+        let scale = Transform::scale(Vec3(2.0, 2.0, 2.0)).unwrap();
+        let translate = Transform::translate(Vec3(1.0, 0.0, 0.0));
+        let p = Vec3(1.0, 1.0, 0.0);
+        let scale_first = scale.then(&translate);
+        assert_vec_close(scale_first.point(p), Vec3(3.0, 2.0, 0.0));
+        assert_vec_close(translate.then(&scale).point(p), Vec3(4.0, 2.0, 0.0));
+        assert_vec_close(scale_first.inverse().point(Vec3(3.0, 2.0, 0.0)), p);
+    }
+
+    #[test]
+    fn from_parts_matches_composed_transforms() {
+        let (translation, axis, degrees, scale) = (
+            Vec3(1.0, 2.0, -3.0),
+            Vec3(0.0, 1.0, 1.0),
+            70.0,
+            Vec3(2.0, -1.0, 0.5),
+        );
+        let parts = Transform::from_parts(
+            translation,
+            Quaternion::from_axis_angle(axis, degrees),
+            scale,
+        );
+        let composed = Transform::scale(scale)
+            .unwrap()
+            .then(&Transform::rotate(axis, degrees))
+            .then(&Transform::translate(translation));
+        assert_close(parts.matrix(), composed.matrix());
+        assert_close(parts.inverse().matrix(), composed.inverse().matrix());
+    }
+
+    #[test]
+    fn slerp_goes_from_one_rotation_to_the_other() {
+        let (axis_a, axis_b) = (Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 1.0));
+        let a = Quaternion::from_axis_angle(axis_a, 30.0);
+        let b = Quaternion::from_axis_angle(axis_b, 100.0);
+        assert_close(
+            &a.slerp(&b, 0.0).to_matrix(),
+            &Matrix4::rotation(axis_a, 30.0),
+        );
+        assert_close(
+            &a.slerp(&b, 1.0).to_matrix(),
+            &Matrix4::rotation(axis_b, 100.0),
+        );
+
+        // About a single axis the angle changes at a constant speed
+        let start = Quaternion::from_axis_angle(axis_b, 0.0);
+        let end = Quaternion::from_axis_angle(axis_b, 90.0);
+        assert_close(
+            &start.slerp(&end, 0.25).to_matrix(),
+            &Matrix4::rotation(axis_b, 22.5),
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::f64::consts::PI;
+
+use crate::*;
+
+/// Probability density over directions that can also be sampled
+pub trait Pdf {
+    fn value(&self, direction: &Vec3) -> f64;
+
+    /// Direction for a 2D sample in [0, 1)^2
+    fn generate(&self, u: (f64, f64)) -> Vec3;
+}
+
+pub struct CosinePdf {
+    uvw: ONB,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> Self {
+        Self {
+            uvw: ONB::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = direction.unit_vector().dot(self.uvw.w());
+        if cosine <= 0.0 {
+            0.0
+        } else {
+            cosine / PI
+        }
+    }
+
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        self.uvw.local(&Vec3::sample_cosine_direction(u))
+    }
+}
+
+/// Uniform over all directions
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        0.25 / PI
+    }
+
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        Vec3::sample_unit_vector(u)
+    }
+}
+
+/// Directions from origin towards an object, usually a light
+pub struct HittablePdf<'a> {
+    object: &'a dyn Hittable,
+    origin: Point3,
+    time: f64,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(object: &'a dyn Hittable, origin: Point3, time: f64) -> Self {
+        Self {
+            object,
+            origin,
+            time,
+        }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.object
+            .pdf_value(&Ray::new(self.origin, *direction, self.time))
+    }
+
+    fn generate(&self, u: (f64, f64)) -> Vec3 {
+        self.object.random(&self.origin, self.time, u)
+    }
+}
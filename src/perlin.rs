@@ -1,5 +1,4 @@
-use rand::rngs::ThreadRng;
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
 
 use crate::*;
 
@@ -58,16 +57,15 @@ impl Perlin {
         accum
     }
 
-    pub fn new() -> Self {
-        let mut rng = thread_rng();
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         let mut ranvec: Vec<Vec3> = Vec::with_capacity(POINT_COUNT);
         for _ in 0..POINT_COUNT {
-            ranvec.push(Vec3::random_range(-1.0, 1.0).unit_vector());
+            ranvec.push(Vec3::random_range(-1.0, 1.0, rng).unit_vector());
         }
 
-        let perm_x = Self::generate_perm(&mut rng);
-        let perm_y = Self::generate_perm(&mut rng);
-        let perm_z = Self::generate_perm(&mut rng);
+        let perm_x = Self::generate_perm(rng);
+        let perm_y = Self::generate_perm(rng);
+        let perm_z = Self::generate_perm(rng);
 
         Self {
             ranvec,
@@ -77,7 +75,7 @@ impl Perlin {
         }
     }
 
-    fn generate_perm(rng: &mut ThreadRng) -> Vec<usize> {
+    fn generate_perm<R: Rng + ?Sized>(rng: &mut R) -> Vec<usize> {
         let mut p: Vec<usize> = (0..POINT_COUNT).collect();
 
         for i in (1..POINT_COUNT).rev() {
@@ -89,18 +87,9 @@ impl Perlin {
     }
 }
 
-fn trilinear_interp(c: &[[[f64; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
-    let mut accum = 0.0;
-    for i in 0..2 {
-        for j in 0..2 {
-            for k in 0..2 {
-                accum += (i as f64 * u + (1.0 - i as f64) * (1.0 - u))
-                    * (j as f64 * v + (1.0 - j as f64) * (1.0 - v))
-                    * (k as f64 * w + (1.0 - k as f64) * (1.0 - w))
-                    * c[i][j][k];
-            }
-        }
+// Always the same noise, so textures don't change with the render's seed
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new(&mut SampleRng::seed_from_u64(0))
     }
-
-    accum
 }
@@ -0,0 +1,419 @@
+use std::sync::Arc;
+
+use crate::*;
+
+// Width and height of the tiles workers are given
+const TILE_SIZE: u32 = 32;
+
+/// Renders a world as seen by a camera into a framebuffer
+///
+/// Samples are taken in passes of increasing size, so a usable image is available early.
+/// With a noise target, later passes skip the pixels that already meet it.
+pub struct Renderer {
+    world: World,
+    camera: Arc<Camera>,
+    config: Config,
+    threads: usize,
+    cancellation: CancellationToken,
+    resume: Option<Resume>,
+}
+
+// Render to continue from
+struct Resume {
+    framebuffer: Framebuffer,
+    // Samples per pixel of the passes that are done, and the tiles done of the next one
+    samples_done: u32,
+    tiles_done: Vec<Tile>,
+}
+
+/// How far along a render is, passed to the callback of `Renderer::render_with`
+pub struct Progress<'a> {
+    // Counted from 1
+    pub pass: usize,
+    pub passes: usize,
+    // Pixels of the current pass that are done, out of those sampled in it
+    pub pixels_done: u32,
+    pub pixels: u32,
+    // Samples per pixel of the passes done, and the tiles done of the one under way, which is
+    // where a resumed render would pick up
+    pub samples_done: u32,
+    pub tiles_done: &'a [Tile],
+    pub framebuffer: &'a Framebuffer,
+}
+
+impl Progress<'_> {
+    pub fn pass_done(&self) -> bool {
+        self.pixels_done == self.pixels
+    }
+}
+
+impl Renderer {
+    pub fn new(world: World, camera: Arc<Camera>, config: Config) -> Self {
+        Self {
+            world,
+            camera,
+            config,
+            threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+            cancellation: CancellationToken::new(),
+            resume: None,
+        }
+    }
+
+    /// Number of render threads, one per CPU by default
+    pub fn threads(mut self, threads: usize) -> Self {
+        assert!(threads > 0);
+        self.threads = threads;
+        self
+    }
+
+    /// Token that stops the render when cancelled, keeping the tiles already under way
+    pub fn cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = cancellation;
+        self
+    }
+
+    /// Continues a render of the same config from the framebuffer it had made, as reported by
+    /// the progress of `render_with`
+    ///
+    /// Fails if the framebuffer or the samples done can't come from a render of the config.
+    pub fn resume(
+        mut self,
+        framebuffer: Framebuffer,
+        samples_done: u32,
+        tiles_done: Vec<Tile>,
+    ) -> Result<Self, String> {
+        let config = &self.config;
+        if framebuffer.width() != config.image_width || framebuffer.height() != config.image_height
+        {
+            return Err(format!(
+                "a {}x{} framebuffer can't continue a {}x{} render",
+                framebuffer.width(),
+                framebuffer.height(),
+                config.image_width,
+                config.image_height
+            ));
+        }
+        if !is_pass_end(config.samples_per_pixel, samples_done) {
+            return Err(format!(
+                "{} samples per pixel is not where a pass of {} ends",
+                samples_done, config.samples_per_pixel
+            ));
+        }
+        self.resume = Some(Resume {
+            framebuffer,
+            samples_done,
+            tiles_done,
+        });
+        Ok(self)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn render(&self) -> (Framebuffer, WorkerStats) {
+        self.render_with(|_| {})
+    }
+
+    /// Renders, calling progress after every tile of every pass, and returns the image with
+    /// what the workers did for it
+    ///
+    /// Passes without any pixels left to sample are skipped. Once cancelled, the tiles under
+    /// way are finished and the rest are skipped, ending the pass and the render.
+    pub fn render_with<F: FnMut(Progress)>(&self, mut progress: F) -> (Framebuffer, WorkerStats) {
+        let config = &self.config;
+        let worker_pool = WorkerPool::spawn(
+            self.threads,
+            self.world.clone(),
+            self.camera.clone(),
+            config.clone(),
+            self.cancellation.clone(),
+        );
+        let ramp = sampling_ramp(config.samples_per_pixel);
+        let tiles = Tile::hilbert_order(config.image_width, config.image_height, TILE_SIZE);
+
+        let (mut framebuffer, samples_done, resumed_tiles) = match &self.resume {
+            Some(resume) => (
+                resume.framebuffer.clone(),
+                resume.samples_done,
+                &resume.tiles_done[..],
+            ),
+            None => (
+                Framebuffer::new(config.image_width, config.image_height),
+                0,
+                &[][..],
+            ),
+        };
+        let mut first_sample = 0;
+        for (pass, s) in ramp.iter().enumerate() {
+            if first_sample < samples_done {
+                first_sample += s;
+                continue;
+            }
+            if self.cancellation.is_cancelled() {
+                break;
+            }
+            // Tiles an interrupted render got through in this pass are already in the image
+            let resumed_tiles = if first_sample == samples_done {
+                resumed_tiles
+            } else {
+                &[]
+            };
+            let jobs: Vec<TileJob> = tiles
+                .iter()
+                .filter(|tile| !resumed_tiles.contains(tile))
+                .filter_map(|tile| {
+                    let pixels: Vec<(u32, u32)> = tile
+                        .pixels()
+                        .filter(|&(x, y)| match config.noise_target {
+                            Some(noise_target) => framebuffer.error(x, y) > noise_target,
+                            None => true,
+                        })
+                        .collect();
+                    if pixels.is_empty() {
+                        return None;
+                    }
+                    Some(TileJob {
+                        tile: *tile,
+                        pixels,
+                        first_sample,
+                        samples: *s,
+                    })
+                })
+                .collect();
+            let sent: Vec<(Tile, u32)> = jobs
+                .iter()
+                .map(|job| (job.tile, job.pixels.len() as u32))
+                .collect();
+            let pixels = sent.iter().map(|(_, pixels)| pixels).sum();
+
+            std::thread::scope(|scope| {
+                let worker_pool = &worker_pool;
+                // Sent from another thread, as the queues only hold a few tiles at a time
+                scope.spawn(move || {
+                    for job in jobs {
+                        worker_pool.send_job(job);
+                    }
+                });
+
+                // Tiles are added in the order they were sent, so each pixel sums the samples
+                // around it in the same order however the workers were scheduled, and the
+                // image is the same to the bit
+                let mut waiting: Vec<FilmTile> = vec![];
+                let mut pixels_done = 0;
+                let mut tiles_done = resumed_tiles.to_vec();
+                let mut skipped = false;
+                for (i, (tile, tile_pixels)) in sent.iter().enumerate() {
+                    let film_tile = loop {
+                        if let Some(i) = waiting.iter().position(|waiting| waiting.tile == *tile) {
+                            break waiting.swap_remove(i);
+                        }
+                        waiting.push(worker_pool.recv_film_tile());
+                    };
+                    if film_tile.is_empty() {
+                        skipped = true;
+                    } else {
+                        framebuffer.add_tile(&film_tile);
+                        tiles_done.push(*tile);
+                    }
+                    pixels_done += tile_pixels;
+                    let pass_complete = !skipped && i == sent.len() - 1;
+                    progress(Progress {
+                        pass: pass + 1,
+                        passes: ramp.len(),
+                        pixels_done,
+                        pixels,
+                        samples_done: if pass_complete {
+                            first_sample + s
+                        } else {
+                            first_sample
+                        },
+                        tiles_done: if pass_complete { &[] } else { &tiles_done },
+                        framebuffer: &framebuffer,
+                    });
+                }
+            });
+            first_sample += s;
+        }
+        (framebuffer, worker_pool.shutdown())
+    }
+}
+
+/// Whether a render of samples_per_pixel has taken samples per pixel after some of its passes,
+/// none included
+pub(crate) fn is_pass_end(samples_per_pixel: u32, samples: u32) -> bool {
+    let mut end = 0;
+    samples == 0
+        || sampling_ramp(samples_per_pixel).into_iter().any(|pass| {
+            end += pass;
+            end == samples
+        })
+}
+
+// Splits the samples into passes that double in size, the first being at most 64
+fn sampling_ramp(mut total_samples: u32) -> Vec<u32> {
+    let mut sampling_ramp = vec![];
+    let mut cur: u32;
+    while total_samples > 64 {
+        cur = total_samples / 2;
+        total_samples -= cur;
+        sampling_ramp.push(cur);
+    }
+    sampling_ramp.push(total_samples);
+    sampling_ramp.into_iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    fn render(config: &Config, threads: usize) -> Framebuffer {
+        let (world, camera) = cornell_box(config);
+        let (framebuffer, _) = Renderer::new(world, camera, config.clone())
+            .threads(threads)
+            .render();
+        framebuffer
+    }
+
+    #[test]
+    fn images_are_the_same_for_any_number_of_threads() {
+        let config = Config {
+            image_width: 40,
+            image_height: 36,
+            samples_per_pixel: 8,
+            ..Config::default()
+        };
+        // Tiles of a wide filter overlap, and adaptive passes depend on the earlier ones
+        let adaptive = Config {
+            samples_per_pixel: 80,
+            filter: FilterKind::Gaussian,
+            noise_target: Some(0.05),
+            ..config.clone()
+        };
+        for config in [config, adaptive].iter() {
+            let one = render(config, 1);
+            let four = render(config, 4);
+            for y in 0..config.image_height {
+                for x in 0..config.image_width {
+                    let (a, b) = (one.color(x, y), four.color(x, y));
+                    assert_eq!(
+                        [a.x(), a.y(), a.z()].map(f64::to_bits),
+                        [b.x(), b.y(), b.z()].map(f64::to_bits),
+                        "pixel ({}, {}) with {:?}",
+                        x,
+                        y,
+                        config
+                    );
+                    assert_eq!(one.samples(x, y), four.samples(x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resumes_only_what_the_config_could_have_rendered() {
+        let config = Config {
+            image_width: 8,
+            image_height: 4,
+            samples_per_pixel: 200,
+            ..Config::default()
+        };
+        let renderer = || {
+            let (world, camera) = cornell_box(&config);
+            Renderer::new(world, camera, config.clone())
+        };
+        // Passes of 200 samples are 50, 50 and 100 long
+        assert!(renderer()
+            .resume(Framebuffer::new(8, 4), 100, vec![])
+            .is_ok());
+        assert!(renderer()
+            .resume(Framebuffer::new(8, 4), 60, vec![])
+            .is_err());
+        assert!(renderer()
+            .resume(Framebuffer::new(4, 8), 100, vec![])
+            .is_err());
+    }
+
+    #[test]
+    fn converged_pixels_stop_being_sampled() {
+        let config = Config {
+            image_width: 24,
+            image_height: 24,
+            samples_per_pixel: 512,
+            noise_target: Some(0.015),
+            ..Config::default()
+        };
+        let (world, camera) = cornell_box(&config);
+        // Samples and noise of every pixel after each pass
+        let mut passes: Vec<Vec<(u32, f64)>> = vec![];
+        Renderer::new(world, camera, config.clone()).render_with(|progress| {
+            if progress.pass_done() {
+                let framebuffer = progress.framebuffer;
+                passes.push(
+                    (0..config.image_height)
+                        .flat_map(|y| (0..config.image_width).map(move |x| (x, y)))
+                        .map(|(x, y)| (framebuffer.samples(x, y), framebuffer.error(x, y)))
+                        .collect(),
+                );
+            }
+        });
+
+        assert_eq!(passes.len(), 4);
+        for pair in passes.windows(2) {
+            let (mut converged, mut sampled) = (0, 0);
+            for (before, after) in pair[0].iter().zip(&pair[1]) {
+                if before.1 <= 0.015 {
+                    assert_eq!(before.0, after.0);
+                    converged += 1;
+                } else {
+                    assert!(after.0 > before.0);
+                    sampled += 1;
+                }
+            }
+            // Or there would be nothing to check
+            assert!(converged > 0 && sampled > 0);
+        }
+    }
+
+    // Panics the first time it is hit, taking down one worker
+    struct PanicsOnce(AtomicBool);
+
+    impl Hittable for PanicsOnce {
+        fn hit(
+            &self,
+            _r: &Ray,
+            _t_min: f64,
+            _t_max: f64,
+            _rng: &mut SampleRng,
+        ) -> Option<HitRecord<'_>> {
+            assert!(
+                self.0.swap(true, Ordering::Relaxed),
+                "hit for the first time"
+            );
+            None
+        }
+
+        fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+            None
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "a render worker panicked")]
+    fn a_worker_panicking_stops_the_render() {
+        let config = Config {
+            image_width: 64,
+            image_height: 64,
+            samples_per_pixel: 1,
+            ..Config::default()
+        };
+        let (_, camera) = cornell_box(&config);
+        let world = World::new(
+            Arc::new(PanicsOnce(AtomicBool::new(false))),
+            Arc::new(SolidColor(Vec3::zero())),
+        );
+        Renderer::new(world, camera, config).threads(2).render();
+    }
+}
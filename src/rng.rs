@@ -0,0 +1,25 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
+
+/// Random number generator every sample draws from, passed down through the render
+///
+/// PCG's output is fixed by its seed, unlike `StdRng` which may change between rand versions.
+pub type SampleRng = Pcg64Mcg;
+
+/// Generator for one sample of a pixel, the same for a seed however the work is scheduled
+pub fn sample_rng(seed: u64, x: u32, y: u32, sample: u32) -> SampleRng {
+    SampleRng::seed_from_u64(sample_seed(seed, x, y, sample))
+}
+
+// Mixes the global seed with a sample's coordinates, so each sample gets its own stream
+pub(crate) fn sample_seed(seed: u64, x: u32, y: u32, sample: u32) -> u64 {
+    let mut hash = seed;
+    for value in &[x, y, sample] {
+        // splitmix64 finalizer
+        hash = (hash ^ *value as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+    }
+    hash
+}
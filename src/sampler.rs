@@ -0,0 +1,441 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Source of the random values a path is traced with, one dimension at a time
+///
+/// Every sample asks for the same dimensions in the same order: its position in the pixel,
+/// the lens and the time, then a light and a material sample at every bounce. Besides
+/// independent random numbers, samplers can spread each dimension evenly over the samples
+/// of a pixel, which converges faster.
+pub trait Sampler {
+    /// Starts over at the first dimension of sample `index` of pixel (x, y)
+    fn start_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// The samplers a render can be configured with
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    Independent,
+    // Jittered strata, which need the number of samples up front
+    Stratified,
+    // Scrambled digit by digit, which makes it the slowest
+    Halton,
+    // Owen scrambled
+    Sobol,
+}
+
+impl SamplerKind {
+    pub const NAMES: [&'static str; 4] = ["independent", "stratified", "halton", "sobol"];
+
+    /// A sampler for renders with this seed and number of samples per pixel
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        let state = SamplerState::new(seed);
+        match self {
+            Self::Independent => Box::new(IndependentSampler(state)),
+            Self::Stratified => Box::new(StratifiedSampler {
+                state,
+                samples: samples_per_pixel.max(1),
+            }),
+            Self::Halton => Box::new(HaltonSampler(state)),
+            Self::Sobol => Box::new(SobolSampler(state)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "independent" => Ok(Self::Independent),
+            "stratified" => Ok(Self::Stratified),
+            "halton" => Ok(Self::Halton),
+            "sobol" => Ok(Self::Sobol),
+            _ => Err(format!("no sampler called {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index = *self as usize;
+        f.write_str(Self::NAMES[index])
+    }
+}
+
+// Where a sampler is, and random numbers for whatever its sequence doesn't cover
+struct SamplerState {
+    seed: u64,
+    x: u32,
+    y: u32,
+    index: u32,
+    dimension: u32,
+    rng: SampleRng,
+}
+
+impl SamplerState {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            x: 0,
+            y: 0,
+            index: 0,
+            dimension: 0,
+            rng: sample_rng(seed, 0, 0, 0),
+        }
+    }
+
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dimension = 0;
+        // Apart from the stream paths draw from, so the two don't correlate
+        self.rng = sample_rng(!self.seed, x, y, index);
+    }
+
+    // Hash of the pixel and the next dimension, the same for every sample of the pixel
+    fn next_dimension(&mut self) -> u64 {
+        let hash = sample_seed(self.seed, self.x, self.y, self.dimension);
+        self.dimension += 1;
+        hash
+    }
+}
+
+/// Independent uniform random numbers
+struct IndependentSampler(SamplerState);
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.0.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.0.rng.gen(), self.0.rng.gen())
+    }
+}
+
+/// One sample in each of evenly sized strata, at a random spot within it
+///
+/// Each dimension of a pixel visits its strata in its own random order, so dimensions
+/// don't correlate.
+struct StratifiedSampler {
+    state: SamplerState,
+    samples: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let hash = self.state.next_dimension();
+        let stratum = permute(self.state.index % self.samples, self.samples, hash as u32);
+        (stratum as f64 + self.state.rng.gen::<f64>()) / self.samples as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        // A grid with at least one cell per sample, some are left empty if that isn't a square
+        let columns = (self.samples as f64).sqrt().ceil() as u32;
+        let rows = self.samples.div_ceil(columns);
+        let hash = self.state.next_dimension();
+        let cell = permute(self.state.index % self.samples, columns * rows, hash as u32);
+        (
+            ((cell % columns) as f64 + self.state.rng.gen::<f64>()) / columns as f64,
+            ((cell / columns) as f64 + self.state.rng.gen::<f64>()) / rows as f64,
+        )
+    }
+}
+
+// Bases of the Halton dimensions, later ones are independent random numbers
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// The Halton sequence, with its digits Owen scrambled differently for each pixel
+///
+/// Without scrambling the large bases of later dimensions barely move over the first
+/// samples, which correlates them.
+struct HaltonSampler(SamplerState);
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.0.dimension as usize;
+        let hash = self.0.next_dimension();
+        match PRIMES.get(dimension) {
+            Some(base) => scrambled_radical_inverse(*base, self.0.index, hash),
+            None => self.0.rng.gen(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Mirrors the digits of index in base around the decimal point, permuting each digit
+// depending on the ones before it. Permutations rather than pbrt's random digit shifts,
+// which leave the first samples of large bases bunched together.
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut digit_value = inverse_base;
+    let mut result = 0.0;
+    // Digits seen so far and how many, which pick the permutation of the next one
+    let mut prefix = 0u64;
+    let mut position = 0u64;
+    // Leading zeros are scrambled too, down to the 32 bits of precision Sobol points have
+    while digit_value * 4_294_967_296.0 >= 1.0 {
+        let digit = index % base;
+        let permutation = mix_bits(seed ^ prefix ^ (position << 56));
+        result += permute(digit, base, permutation as u32) as f64 * digit_value;
+        prefix = prefix.wrapping_mul(base as u64).wrapping_add(digit as u64);
+        position += 1;
+        index /= base;
+        digit_value *= inverse_base;
+    }
+    result.min(1.0 - f64::EPSILON / 2.0)
+}
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Owen scrambled Sobol points, after Burley's "Practical Hash-based Owen Scrambling"
+///
+/// Every 2D dimension takes the first two Sobol dimensions at a shuffled index, so no
+/// table of direction numbers is needed and dimensions stay decorrelated.
+struct SobolSampler(SamplerState);
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.0.next_dimension();
+        let index = nested_uniform_scramble(self.0.index, seed as u32);
+        to_unit(nested_uniform_scramble(
+            index.reverse_bits(),
+            (seed >> 32) as u32,
+        ))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.0.next_dimension();
+        let index = nested_uniform_scramble(self.0.index, seed as u32);
+        let scramble = sample_seed(seed, 0, 0, 0);
+        (
+            to_unit(nested_uniform_scramble(
+                index.reverse_bits(),
+                scramble as u32,
+            )),
+            to_unit(nested_uniform_scramble(
+                sobol_second_dimension(index),
+                (scramble >> 32) as u32,
+            )),
+        )
+    }
+}
+
+fn to_unit(value: u32) -> f64 {
+    value as f64 / (1u64 << 32) as f64
+}
+
+// The second Sobol dimension, whose direction numbers are Pascal's triangle mod 2
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut result = 0;
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+// Owen scrambling, which randomly flips every bit depending on the bits above it
+fn nested_uniform_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+// Hash where each bit depends only on the bits below it
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x ^= x.wrapping_mul(0x3d20_adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x0552_6c56);
+    x ^= x.wrapping_mul(0x53a2_2864);
+    x
+}
+
+// Element i of a random permutation of 0..length chosen by seed, from Kensler's
+// "Correlated Multi-Jittered Sampling"
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= mask;
+        i ^= i >> 5;
+        if i < length {
+            return (i.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Exactly one value in each of strata equal parts of [0, 1)
+    fn assert_stratified(values: &[f64], strata: usize) {
+        let mut counts = vec![0; strata];
+        for value in values {
+            counts[(value * strata as f64) as usize] += 1;
+        }
+        assert!(counts.iter().all(|count| *count == 1), "{:?}", values);
+    }
+
+    // Samples of pixel (x, y), each the values of a 1D, a 2D and another 1D dimension
+    fn samples(
+        sampler: &mut dyn Sampler,
+        (x, y): (u32, u32),
+        count: u32,
+    ) -> Vec<(f64, (f64, f64), f64)> {
+        (0..count)
+            .map(|index| {
+                sampler.start_sample(x, y, index);
+                (sampler.get_1d(), sampler.get_2d(), sampler.get_1d())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn values_stay_in_the_unit_interval() {
+        for &kind in [
+            SamplerKind::Independent,
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ]
+        .iter()
+        {
+            let mut sampler = kind.build(3, 100);
+            for &pixel in [(0, 0), (17, 5), (u32::MAX, u32::MAX)].iter() {
+                for index in (0..300).chain(u32::MAX - 10..=u32::MAX) {
+                    sampler.start_sample(pixel.0, pixel.1, index);
+                    // Beyond the dimensions Halton has bases for
+                    for _ in 0..80 {
+                        let value = sampler.get_1d();
+                        let (u, v) = sampler.get_2d();
+                        for value in [value, u, v].iter() {
+                            assert!((0.0..1.0).contains(value), "{} from {}", value, kind);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_sampler_fills_every_stratum() {
+        let mut sampler = SamplerKind::Stratified.build(1, 16);
+        for &pixel in [(0, 0), (3, 9)].iter() {
+            let samples = samples(&mut *sampler, pixel, 16);
+            let first: Vec<f64> = samples.iter().map(|sample| sample.0).collect();
+            let last: Vec<f64> = samples.iter().map(|sample| sample.2).collect();
+            assert_stratified(&first, 16);
+            assert_stratified(&last, 16);
+            // One sample in each cell of a 4 by 4 grid
+            let cells: Vec<f64> = samples
+                .iter()
+                .map(|(_, (u, v), _)| ((v * 4.0).floor() * 4.0 + (u * 4.0).floor()) / 16.0)
+                .collect();
+            assert_stratified(&cells, 16);
+        }
+    }
+
+    #[test]
+    fn halton_sampler_is_stratified_in_its_bases() {
+        let mut sampler = SamplerKind::Halton.build(1, 0);
+        for &pixel in [(0, 0), (3, 9)].iter() {
+            // Dimensions 0, 1 and 2 have bases 2, 3 and 5
+            let samples = samples(&mut *sampler, pixel, 32);
+            let base_2: Vec<f64> = samples.iter().map(|sample| sample.0).collect();
+            assert_stratified(&base_2, 32);
+            let base_3: Vec<f64> = samples[..27].iter().map(|sample| (sample.1).0).collect();
+            assert_stratified(&base_3, 27);
+            let base_5: Vec<f64> = samples[..25].iter().map(|sample| (sample.1).1).collect();
+            assert_stratified(&base_5, 25);
+        }
+    }
+
+    #[test]
+    fn sobol_sampler_is_stratified_in_every_elementary_interval() {
+        let mut sampler = SamplerKind::Sobol.build(1, 0);
+        for &pixel in [(0, 0), (3, 9)].iter() {
+            let samples = samples(&mut *sampler, pixel, 64);
+            let first: Vec<f64> = samples.iter().map(|sample| sample.0).collect();
+            assert_stratified(&first, 64);
+            // 64 points form a (0, 6, 2)-net, with one point in each box of 64 equal boxes
+            for columns in [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0].iter() {
+                let rows = 64.0 / columns;
+                let cells: Vec<f64> = samples
+                    .iter()
+                    .map(|(_, (u, v), _)| {
+                        ((v * rows).floor() * columns + (u * columns).floor()) / 64.0
+                    })
+                    .collect();
+                assert_stratified(&cells, 64);
+            }
+        }
+    }
+}
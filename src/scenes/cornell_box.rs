@@ -68,14 +68,12 @@ pub fn cornell_box(config: &Config) -> (World, Arc<Camera>) {
     )));
 
     let cube1 = Cube::new(Vec3::zero(), Vec3(165.0, 330.0, 165.0), white.clone());
-    let cube1 = YRotation::new(cube1, 15.0);
-    let cube1 = Translation::new(cube1, Vec3(265.0, 0.0, 295.0));
-    world.add(cube1);
+    let transform = Transform::rotate_y(15.0).then(&Transform::translate(Vec3(265.0, 0.0, 295.0)));
+    world.add(Instance::new(cube1, transform));
 
     let cube2 = Cube::new(Vec3::zero(), Vec3(165.0, 165.0, 165.0), white.clone());
-    let cube2 = YRotation::new(cube2, -18.0);
-    let cube2 = Translation::new(cube2, Vec3(130.0, 0.0, 65.0));
-    world.add(cube2);
+    let transform = Transform::rotate_y(-18.0).then(&Transform::translate(Vec3(130.0, 0.0, 65.0)));
+    world.add(Instance::new(cube2, transform));
 
     let world = World::new(Arc::new(world), SolidColor::new(0.0, 0.0, 0.0));
     (world, camera)
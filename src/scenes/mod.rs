@@ -92,7 +92,6 @@ pub fn random_scene(config: &Config) -> (World, Arc<Camera>) {
         SolidColor::new(0.9, 0.9, 0.9),
     );
     let noise = NoiseTexture::new(10.0);
-    let light = DiffuseLight::from_texture(SolidColor::new(1.0, 1.0, 1.0));
     world.add(Sphere::new(
         Vec3(0.0, -1000.0, 0.0),
         1000.0,
@@ -144,4 +143,4 @@ pub fn two_spheres() -> Arc<dyn Hittable + Send + Sync> {
     ));
 
     Arc::new(world)
-}
\ No newline at end of file
+}
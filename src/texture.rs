@@ -60,14 +60,14 @@ pub struct NoiseTexture {
 impl NoiseTexture {
     pub fn new(scale: f64) -> Arc<Self> {
         Arc::new(Self {
-            perlin: Perlin::new(),
+            perlin: Perlin::default(),
             scale,
         })
     }
 }
 
 impl Texture for NoiseTexture {
-    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color3 {
         Vec3(1.0, 1.0, 1.0)
             //* 0.5 * (1.0 + (self.scale * p.z() + 10.0 * self.perlin.turb(p, 7)).sin())
         * self.perlin.turb(&(self.scale * *p), 7)
@@ -83,7 +83,11 @@ pub struct ImageTexture {
 
 impl ImageTexture {
     pub fn from_file<P: AsRef<Path>>(path: P) -> Arc<Self> {
-        let im = image::open(path).unwrap();
+        Self::try_from_file(path).unwrap()
+    }
+
+    pub fn try_from_file<P: AsRef<Path>>(path: P) -> Result<Arc<Self>, image::ImageError> {
+        let im = image::open(path)?;
         let width = im.width() as usize;
         let height = im.height() as usize;
         let mut data = im.to_rgb().into_raw();
@@ -92,19 +96,19 @@ impl ImageTexture {
             let float_datum = *datum as f64 * COLOR_SCALE;
             *datum = ((float_datum * float_datum) * 255.0) as u8;
         }
-        Arc::new(Self {
+        Ok(Arc::new(Self {
             data,
             width,
             height,
             bytes_per_scanline: width * 3,
-        })
+        }))
     }
 }
 
 const COLOR_SCALE: f64 = 1.0 / 255.0;
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: &Point3) -> Color3 {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color3 {
         if self.data.is_empty() {
             return Vec3(0.0, 1.0, 1.0);
         }
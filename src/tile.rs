@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+/// Rectangle of pixels rendered as one job, with rows counted from the top
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Covers an image with tiles of size by size pixels, smaller at the right and bottom edges
+    ///
+    /// Tiles follow a Hilbert curve, so consecutive ones are neighbors and share what they hit.
+    pub fn hilbert_order(image_width: u32, image_height: u32, size: u32) -> Vec<Tile> {
+        assert!(size > 0);
+        let columns = image_width.div_ceil(size);
+        let rows = image_height.div_ceil(size);
+        let side = columns.max(rows).next_power_of_two();
+
+        let mut tiles: Vec<(u64, Tile)> = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(|(column, row)| {
+                let (x, y) = (column * size, row * size);
+                let tile = Tile {
+                    x,
+                    y,
+                    width: size.min(image_width - x),
+                    height: size.min(image_height - y),
+                };
+                (hilbert_index(side, column, row), tile)
+            })
+            .collect();
+        tiles.sort_by_key(|(index, _)| *index);
+        tiles.into_iter().map(|(_, tile)| tile).collect()
+    }
+
+    /// Pixels of the tile row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let tile = *self;
+        (tile.y..tile.y + tile.height)
+            .flat_map(move |y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+    }
+}
+
+// Distance of (x, y) along a Hilbert curve through a side by side grid, side a power of two
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve inside it starts and ends in the right corners
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
@@ -1,16 +1,15 @@
 use std::ops;
 
 use image::*;
-use rand::random;
-use rand::thread_rng;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::util::Clamp;
 
 /// Simple 3 data point structure
 ///
 /// Useful for points, vectors, normals, and colors
-#[derive(Copy, Debug, Clone)]
+#[derive(Copy, Debug, Clone, Serialize, Deserialize)]
 pub struct Vec3(pub f64, pub f64, pub f64);
 
 pub type Point3 = Vec3;
@@ -22,15 +21,11 @@ impl Vec3 {
     }
 
     pub fn infinity() -> Self {
-        Self(std::f64::INFINITY, std::f64::INFINITY, std::f64::INFINITY)
+        Self(f64::INFINITY, f64::INFINITY, f64::INFINITY)
     }
 
     pub fn neg_infinity() -> Self {
-        Self(
-            std::f64::NEG_INFINITY,
-            std::f64::NEG_INFINITY,
-            std::f64::NEG_INFINITY,
-        )
+        Self(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY)
     }
 
     pub fn x(&self) -> f64 {
@@ -96,12 +91,16 @@ impl Vec3 {
         *self / self.length()
     }
 
-    pub fn random() -> Self {
-        Vec3(random::<f64>(), random::<f64>(), random::<f64>())
+    // Perceived brightness of a linear Rec. 709 color
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
     }
 
-    pub fn random_range(min: f64, max: f64) -> Self {
-        let mut rng = thread_rng();
+    pub fn random_range<R: Rng + ?Sized>(min: f64, max: f64, rng: &mut R) -> Self {
         Vec3(
             rng.gen_range(min, max),
             rng.gen_range(min, max),
@@ -109,27 +108,31 @@ impl Vec3 {
         )
     }
 
-    pub fn random_in_unit_sphere() -> Self {
-        Self::random_unit_vector() * random::<f64>()
+    pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self::random_unit_vector(rng) * rng.gen::<f64>()
     }
 
-    pub fn random_unit_vector() -> Self {
-        let mut rng = thread_rng();
-        let a = rng.gen_range(0.0, std::f64::consts::PI * 2.0);
-        let z = rng.gen_range(-1.0, 1.0);
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        Self::sample_unit_vector((rng.gen(), rng.gen()))
+    }
+
+    // The functions below map a 2D sample in [0, 1)^2 from a Sampler onto a distribution
+
+    pub fn sample_unit_vector(u: (f64, f64)) -> Self {
+        let a = u.0 * std::f64::consts::PI * 2.0;
+        let z = 1.0 - 2.0 * u.1;
         let r = (1.0f64 - z * z).sqrt();
         Vec3(r * a.cos(), r * a.sin(), z)
     }
 
-    pub fn random_in_unit_disk() -> Self {
-        let mut rng = thread_rng();
-        let angle = rng.gen_range(0.0, std::f64::consts::PI * 2.0);
-        let length: f64 = rng.gen();
+    pub fn sample_in_unit_disk(u: (f64, f64)) -> Self {
+        let angle = u.0 * std::f64::consts::PI * 2.0;
+        let length = u.1;
         Vec3(length * angle.cos(), length * angle.sin(), 0.0)
     }
 
-    pub fn random_in_hemisphere(normal: Vec3) -> Self {
-        let in_unit_sphere = Self::random_in_unit_sphere();
+    pub fn random_in_hemisphere<R: Rng + ?Sized>(normal: Vec3, rng: &mut R) -> Self {
+        let in_unit_sphere = Self::random_in_unit_sphere(rng);
         if normal.dot(in_unit_sphere) > 0.0 {
             in_unit_sphere
         } else {
@@ -137,9 +140,8 @@ impl Vec3 {
         }
     }
 
-    pub fn random_cosine_direction() -> Self {
-        let r1 = random::<f64>();
-        let r2 = random::<f64>();
+    pub fn sample_cosine_direction(u: (f64, f64)) -> Self {
+        let (r1, r2) = u;
         let z = (1.0 - r2).sqrt();
 
         let phi = 2.0 * std::f64::consts::PI * r1;
@@ -148,6 +150,18 @@ impl Vec3 {
 
         Vec3(x, y, z)
     }
+
+    // Uniform direction within the cone subtended by a sphere, around the z axis
+    pub fn sample_to_sphere(radius: f64, distance_squared: f64, u: (f64, f64)) -> Self {
+        let (r1, r2) = u;
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * (1.0 - z * z).sqrt();
+        let y = phi.sin() * (1.0 - z * z).sqrt();
+
+        Vec3(x, y, z)
+    }
 }
 
 impl From<Vec3> for image::Rgb<u8> {
@@ -1,4 +1,4 @@
-use rand::random;
+use rand::Rng;
 
 use crate::*;
 
@@ -24,13 +24,13 @@ impl ConstantMedium {
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
         let mut rec1 = self
             .boundary
-            .hit(r, std::f64::NEG_INFINITY, std::f64::INFINITY)?;
+            .hit(r, f64::NEG_INFINITY, f64::INFINITY, rng)?;
         let mut rec2 = self
             .boundary
-            .hit(r, rec1.distance + 0.0001, std::f64::INFINITY)?;
+            .hit(r, rec1.distance + 0.0001, f64::INFINITY, rng)?;
 
         rec1.distance = rec1.distance.max(t_min);
         rec2.distance = rec2.distance.min(t_max);
@@ -42,7 +42,7 @@ impl Hittable for ConstantMedium {
         rec1.distance = rec1.distance.max(0.0);
 
         let distance_inside_boundary = rec2.distance - rec1.distance;
-        let hit_distance = self.neg_inv_density * random::<f64>().ln();
+        let hit_distance = self.neg_inv_density * rng.gen::<f64>().ln();
 
         if hit_distance > distance_inside_boundary {
             return None;
@@ -55,7 +55,7 @@ impl Hittable for ConstantMedium {
             position,
             normal: Vec3(1.0, 0.0, 0.0),
             front_face: true,
-            material: self.phase_function.clone(),
+            material: &*self.phase_function,
             u: 0.0,
             v: 0.0,
         })
@@ -64,4 +64,8 @@ impl Hittable for ConstantMedium {
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.boundary.bounding_box(t0, t1)
     }
+
+    fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        self.boundary.bvh_stats()
+    }
 }
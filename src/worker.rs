@@ -1,93 +1,293 @@
+use std::fmt;
+use std::ops::Add;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, SendTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
 
 use crate::*;
 
+// How often a pool waiting on its workers checks that they are all still alive
+const WORKER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Samples first_sample..first_sample + samples of some of the pixels of a tile
+pub struct TileJob {
+    pub tile: Tile,
+    // Rows counted from the top, like the tile
+    pub pixels: Vec<(u32, u32)>,
+    pub first_sample: u32,
+    pub samples: u32,
+}
+
+/// Shared flag that stops a render early once set
+///
+/// Workers finish the tiles they are on, and hand back the rest without sampling them.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// What workers did, for one worker or added up over all of them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WorkerStats {
+    // Camera, bounce and shadow rays
+    pub rays: u64,
+    pub samples: u64,
+    // Time spent working on jobs rather than waiting for them
+    pub busy: Duration,
+}
+
+impl Add for WorkerStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            rays: self.rays + other.rays,
+            samples: self.samples + other.samples,
+            busy: self.busy + other.busy,
+        }
+    }
+}
+
+impl fmt::Display for WorkerStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let busy = self.busy.as_secs_f64();
+        write!(
+            f,
+            "{} rays for {} samples in {:.1} busy seconds ({:.2} Mrays/s per thread)",
+            self.rays,
+            self.samples,
+            busy,
+            if busy > 0.0 {
+                self.rays as f64 / busy / 1e6
+            } else {
+                0.0
+            }
+        )
+    }
+}
+
 pub struct WorkerPool {
-    // TODO: We can have JoinHandle later return final render stats upon exit
-    workers: Vec<JoinHandle<()>>,
-    color_rx: Receiver<(u32, u32, u32, Color3)>,
-    job_tx: Sender<(u32, u32, u32)>,
+    workers: Vec<JoinHandle<WorkerStats>>,
+    film_tile_rx: Receiver<FilmTile>,
+    job_tx: Sender<TileJob>,
+    // Kept to drop queued jobs on shutdown
+    job_rx: Receiver<TileJob>,
 }
 
 impl WorkerPool {
-    pub fn spawn(num_workers: usize, world: World, camera: Arc<Camera>, config: Config) -> Self {
-        let (color_tx, color_rx) = unbounded::<(u32, u32, u32, Color3)>();
-        let (job_tx, job_rx) = unbounded::<(u32, u32, u32)>();
-        let mut workers: Vec<JoinHandle<()>> = Vec::with_capacity(num_workers);
+    pub fn spawn(
+        num_workers: usize,
+        world: World,
+        camera: Arc<Camera>,
+        config: Config,
+        cancellation: CancellationToken,
+    ) -> Self {
+        // Bounded, so queued jobs and finished tiles take little memory however big the image
+        let (film_tile_tx, film_tile_rx) = bounded::<FilmTile>(2 * num_workers);
+        let (job_tx, job_rx) = bounded::<TileJob>(2 * num_workers);
+        let mut workers: Vec<JoinHandle<WorkerStats>> = Vec::with_capacity(num_workers);
         for _ in 0..num_workers {
             let handle = Worker::spawn(
                 job_rx.clone(),
-                color_tx.clone(),
+                film_tile_tx.clone(),
                 world.clone(),
                 camera.clone(),
                 config.clone(),
+                cancellation.clone(),
             );
             workers.push(handle);
         }
         Self {
-            workers: vec![],
-            color_rx,
+            workers,
+            film_tile_rx,
             job_tx,
+            job_rx,
+        }
+    }
+
+    // Blocks while the queue is full, so finished tiles have to be received at the same time.
+    // Once a worker has died the job is dropped, as recv_film_tile panics anyway
+    pub fn send_job(&self, mut job: TileJob) {
+        loop {
+            match self.job_tx.send_timeout(job, WORKER_CHECK_INTERVAL) {
+                Ok(()) => return,
+                Err(SendTimeoutError::Timeout(unsent)) if !self.worker_died() => job = unsent,
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Waits for the next finished tile
+    ///
+    /// Panics if a worker has died, rather than waiting forever for the tiles it had.
+    pub fn recv_film_tile(&self) -> FilmTile {
+        loop {
+            match self.film_tile_rx.recv_timeout(WORKER_CHECK_INTERVAL) {
+                Ok(film_tile) => return film_tile,
+                Err(RecvTimeoutError::Timeout) => {
+                    assert!(!self.worker_died(), "a render worker panicked")
+                }
+                Err(RecvTimeoutError::Disconnected) => panic!("a render worker panicked"),
+            }
         }
     }
 
-    pub fn send_job(&self, s: u32, u: u32, v: u32) {
-        self.job_tx.send((s, u, v)).unwrap();
+    // Workers only exit before shutdown when they panic
+    fn worker_died(&self) -> bool {
+        self.workers.iter().any(|worker| worker.is_finished())
     }
-    pub fn recv_color(&self) -> (u32, u32, u32, Color3) {
-        self.color_rx.recv().unwrap()
+
+    /// Drops the jobs still queued, waits for the workers to finish the ones they are on and
+    /// exit, and adds up their stats
+    ///
+    /// Tiles that haven't been received are thrown away.
+    pub fn shutdown(self) -> WorkerStats {
+        let Self {
+            workers,
+            film_tile_rx,
+            job_tx,
+            job_rx,
+        } = self;
+        while job_rx.try_recv().is_ok() {}
+        // Workers stop when the queue is closed and empty
+        drop(job_tx);
+        drop(job_rx);
+        // Keep receiving so no worker stays blocked on a full queue, until they have all exited
+        while film_tile_rx.recv().is_ok() {}
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("render worker panicked"))
+            .fold(WorkerStats::default(), |total, stats| total + stats)
     }
 }
 
 pub struct Worker {
-    pub job_rx: Receiver<(u32, u32, u32)>,
-    pub color_tx: Sender<(u32, u32, u32, Color3)>,
+    pub job_rx: Receiver<TileJob>,
+    pub film_tile_tx: Sender<FilmTile>,
     pub world: World,
     pub camera: Arc<Camera>,
     pub config: Config,
+    pub cancellation: CancellationToken,
 }
 
 impl Worker {
     pub fn spawn(
-        job_rx: Receiver<(u32, u32, u32)>,
-        color_tx: Sender<(u32, u32, u32, Color3)>,
+        job_rx: Receiver<TileJob>,
+        film_tile_tx: Sender<FilmTile>,
         world: World,
         camera: Arc<Camera>,
         config: Config,
-    ) -> JoinHandle<()> {
+        cancellation: CancellationToken,
+    ) -> JoinHandle<WorkerStats> {
         std::thread::spawn(move || {
             let worker = Worker {
                 job_rx,
-                color_tx,
+                film_tile_tx,
                 world,
                 camera,
                 config,
+                cancellation,
             };
-            worker.work_until_dead();
+            worker.work_until_dead()
         })
     }
 
-    fn work_until_dead(&self) {
-        while let Ok((s, x, y)) = self.job_rx.recv() {
-            let mut total_color = Vec3::zero();
-            for _ in 0..s {
-                let u = (x as f64 + random::<f64>()) / self.config.image_width as f64;
-                let v = (y as f64 + random::<f64>()) / self.config.image_height as f64;
-                let r = self.camera.get_ray(u, v);
-                total_color += self.world.ray_color(r, self.config.max_depth);
+    fn work_until_dead(&self) -> WorkerStats {
+        let mut stats = WorkerStats::default();
+        let mut sampler = self
+            .config
+            .sampler
+            .build(self.config.seed, self.config.samples_per_pixel);
+        let filter = self.config.filter.build();
+        let height = self.config.image_height;
+        while let Ok(job) = self.job_rx.recv() {
+            let start = Instant::now();
+            let mut film_tile = FilmTile::new(job.tile, &*filter);
+            // Skipped tiles are still sent, as the renderer waits for every tile
+            let pixels = if self.cancellation.is_cancelled() {
+                &[][..]
+            } else {
+                &job.pixels[..]
+            };
+            for &(x, film_y) in pixels {
+                // The camera counts rows from the bottom
+                let y = height - 1 - film_y;
+                for sample in job.first_sample..job.first_sample + job.samples {
+                    sampler.start_sample(x, y, sample);
+                    let mut rng = sample_rng(self.config.seed, x, y, sample);
+                    let (jitter_x, jitter_y) = sampler.get_2d();
+                    let u = (x as f64 + jitter_x) / self.config.image_width as f64;
+                    let v = (y as f64 + jitter_y) / height as f64;
+                    let r = self.camera.get_ray(u, v, &mut *sampler);
+                    let (color, rays) =
+                        self.world
+                            .ray_color(r, &self.config, &mut *sampler, &mut rng);
+                    film_tile.add_sample(
+                        (x, film_y),
+                        (x as f64 + jitter_x, (height - y) as f64 - jitter_y),
+                        color,
+                        &*filter,
+                    );
+                    stats.rays += rays as u64;
+                    stats.samples += 1;
+                }
+            }
+            stats.busy += start.elapsed();
+            // The pool stopped listening, so nobody wants the rest
+            if self.film_tile_tx.send(film_tile).is_err() {
+                break;
             }
-            self.color_tx.send((s, x, y, total_color)).unwrap();
         }
+        stats
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub image_width: u32,
     pub image_height: u32,
     pub samples_per_pixel: u32,
     pub max_depth: u32,
+    // Bounces before paths may be ended early by russian roulette
+    pub roulette_depth: u32,
+    // Renders with the same seed give the same image
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    // How samples are weighted into the pixels around them
+    pub filter: FilterKind,
+    // Pixels stop being sampled once their estimated noise, as a fraction of full brightness
+    // after gamma correction, falls below this
+    pub noise_target: Option<f64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            image_width: 500,
+            image_height: 500,
+            samples_per_pixel: 10,
+            max_depth: 50,
+            roulette_depth: 5,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            filter: FilterKind::Box,
+            noise_target: None,
+        }
+    }
 }
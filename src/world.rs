@@ -1,10 +1,24 @@
+use rand::Rng;
+
 use crate::*;
-use rand::thread_rng;
+
+/// How direct lighting is estimated at non-specular surfaces
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSampling {
+    // Only follow the material, lights are found by chance
+    Material,
+    // Sample a light at every bounce, ignore lights the material hits
+    Lights,
+    // Both, combined with the power heuristic
+    Multiple,
+}
 
 #[derive(Clone)]
 pub struct World {
     root: Arc<dyn Hittable + Send + Sync>,
+    lights: Arc<HitList>,
     background: Arc<dyn Texture + Send + Sync>,
+    light_sampling: LightSampling,
 }
 
 impl World {
@@ -12,55 +26,277 @@ impl World {
         root: Arc<dyn Hittable + Send + Sync>,
         background: Arc<dyn Texture + Send + Sync>,
     ) -> Self {
-        Self { root, background }
+        let lights = Arc::new(HitList {
+            objects: root.emitters(),
+        });
+        Self {
+            root,
+            lights,
+            background,
+            light_sampling: LightSampling::Multiple,
+        }
     }
 
-    pub fn ray_color(&self, ray: Ray, depth: u32) -> Vec3 {
-        // Recursive base case
-        if depth == 0 {
-            return Vec3::zero();
+    /// Statistics of every BVH in the scene, once for each BVH however often it is instanced
+    pub fn bvh_stats(&self) -> Vec<Arc<BvhStats>> {
+        let mut stats: Vec<Arc<BvhStats>> = vec![];
+        for bvh in self.root.bvh_stats() {
+            if !stats.iter().any(|seen| Arc::ptr_eq(seen, &bvh)) {
+                stats.push(bvh);
+            }
         }
+        stats
+    }
 
-        // We hit something
-        if let Some(hit_record) = self.root.hit(&ray, 0.001, std::f64::INFINITY) {
-            if let Some((albedo, scatter_ray, pdf)) = hit_record.material.scatter(&ray, &hit_record)
-            {
-                let mut rng = thread_rng();
-                let on_light = Vec3(
-                    rng.gen_range(213.0, 343.0),
-                    554.0,
-                    rng.gen_range(227.0, 332.0),
-                );
-                let to_light = on_light - hit_record.position;
-                let dist_sqd = to_light.length_squared();
-                let to_light = to_light.unit_vector();
+    pub fn light_sampling(mut self, light_sampling: LightSampling) -> Self {
+        self.light_sampling = light_sampling;
+        self
+    }
 
-                if to_light.dot(hit_record.normal) < 0.0 {
-                    return hit_record.emitted(&ray);
-                }
+    /// Radiance along ray, with sampler giving the light and material samples at each bounce,
+    /// and the number of rays traced for it, shadow rays included
+    pub fn ray_color(
+        &self,
+        mut ray: Ray,
+        config: &Config,
+        sampler: &mut dyn Sampler,
+        rng: &mut SampleRng,
+    ) -> (Color3, u32) {
+        let mut rays = 0;
+        let mut color = Vec3::zero();
+        let mut throughput = Vec3(1.0, 1.0, 1.0);
+        // Density the last bounce chose ray with, or None if ray could not have come from sampling a light
+        let mut material_pdf: Option<f64> = None;
+
+        for depth in 0..config.max_depth {
+            // Taken up front so every bounce uses the same dimensions, whatever it ends up needing
+            let light_sample = sampler.get_2d();
+            let material_sample = sampler.get_2d();
 
-                let light_area = (343.0 - 213.0) * (332.0 - 227.0);
-                // We know the light's normal is exactly on the y axis, so this is fine
-                let light_cos = to_light.y().abs();
-                if light_cos < 0.000001 {
-                    return hit_record.emitted(&ray);
+            rays += 1;
+            let hit_record = match self.root.hit(&ray, 0.001, f64::INFINITY, rng) {
+                Some(hit_record) => hit_record,
+                None => {
+                    // Off into infinity
+                    let (u, v) = get_sphere_uv(&ray.direction);
+                    color += throughput * self.background.value(u, v, &ray.direction);
+                    break;
                 }
+            };
+
+            color +=
+                throughput * hit_record.emitted(&ray) * self.emission_weight(&ray, material_pdf);
 
-                let pdf = dist_sqd / (light_cos * light_area);
-                let scatter_ray = Ray::new(hit_record.position, to_light, ray.time);
+            let scatter = match hit_record.material.scatter(&ray, &hit_record, rng) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            let scatter_pdf = match (scatter.specular_ray, scatter.pdf) {
+                // Specular bounces can't be importance sampled towards lights
+                (Some(specular_ray), _) => {
+                    throughput = throughput * scatter.attenuation;
+                    ray = specular_ray;
+                    material_pdf = None;
+                    if !russian_roulette(&mut throughput, depth, config, rng) {
+                        break;
+                    }
+                    continue;
+                }
+                (None, Some(pdf)) => pdf,
+                (None, None) => break,
+            };
 
-                return albedo
-                    * hit_record
+            // Light sample, standing in for the emission the next bounce would find
+            if self.light_sampling != LightSampling::Material
+                && !self.lights.objects.is_empty()
+                && depth + 1 < config.max_depth
+            {
+                let light_pdf = HittablePdf::new(&*self.lights, hit_record.position, ray.time);
+                let light_ray = Ray::new(
+                    hit_record.position,
+                    light_pdf.generate(light_sample),
+                    ray.time,
+                );
+                let pdf = light_pdf.value(&light_ray.direction);
+                let scattering_pdf =
+                    hit_record
                         .material
-                        .scattering_pdf(&ray, &hit_record, &scatter_ray)
-                    * self.ray_color(scatter_ray, depth - 1)
-                    / pdf;
+                        .scattering_pdf(&ray, &hit_record, &light_ray);
+                if pdf > 0.0 && scattering_pdf > 0.0 {
+                    rays += 1;
+                    if let Some(light_hit) = self.visible_light(&light_ray, rng) {
+                        let weight = match self.light_sampling {
+                            LightSampling::Multiple => {
+                                power_heuristic(pdf, scatter_pdf.value(&light_ray.direction))
+                            }
+                            _ => 1.0,
+                        };
+                        color += throughput
+                            * scatter.attenuation
+                            * scattering_pdf
+                            * light_hit.emitted(&light_ray)
+                            * (weight / pdf);
+                    }
+                }
+            }
+
+            // Material sample
+            let scatter_ray = Ray::new(
+                hit_record.position,
+                scatter_pdf.generate(material_sample),
+                ray.time,
+            );
+            let pdf = scatter_pdf.value(&scatter_ray.direction);
+            if pdf <= 0.0 {
+                break;
             }
-            return hit_record.emitted(&ray);
+            let scattering_pdf =
+                hit_record
+                    .material
+                    .scattering_pdf(&ray, &hit_record, &scatter_ray);
+            throughput = throughput * scatter.attenuation * (scattering_pdf / pdf);
+            ray = scatter_ray;
+            material_pdf = Some(pdf);
+
+            if !russian_roulette(&mut throughput, depth, config, rng) {
+                break;
+            }
+        }
+
+        (color, rays)
+    }
+
+    // The light ray hits first, unless something else is in the way
+    fn visible_light(&self, light_ray: &Ray, rng: &mut SampleRng) -> Option<HitRecord<'_>> {
+        let light_hit = self.lights.hit(light_ray, 0.001, f64::INFINITY, rng)?;
+        if self
+            .root
+            .occluded(light_ray, 0.001, light_hit.distance - 0.001, rng)
+        {
+            return None;
         }
+        Some(light_hit)
+    }
+
+    // How much of the emission found along ray counts, given how the ray was sampled
+    fn emission_weight(&self, ray: &Ray, material_pdf: Option<f64>) -> f64 {
+        match (self.light_sampling, material_pdf) {
+            (_, None) | (LightSampling::Material, _) => 1.0,
+            (LightSampling::Lights, Some(_)) => 0.0,
+            (LightSampling::Multiple, Some(pdf)) => {
+                power_heuristic(pdf, self.lights.pdf_value(ray))
+            }
+        }
+    }
+}
+
+// Randomly ends paths that carry little light, boosting the survivors to stay unbiased
+fn russian_roulette(
+    throughput: &mut Vec3,
+    depth: u32,
+    config: &Config,
+    rng: &mut SampleRng,
+) -> bool {
+    if depth + 1 < config.roulette_depth {
+        return true;
+    }
+    let survival = throughput
+        .x()
+        .max(throughput.y())
+        .max(throughput.z())
+        .min(1.0);
+    if survival <= 0.0 || rng.gen::<f64>() >= survival {
+        return false;
+    }
+    *throughput /= survival;
+    true
+}
+
+// Veach's power heuristic with an exponent of 2
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf = pdf * pdf;
+    let other_pdf = other_pdf * other_pdf;
+    if pdf + other_pdf > 0.0 {
+        pdf / (pdf + other_pdf)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    // A floor lit by a tiny bright light, which suits light sampling,
+    // and a wide dim one right above it, which suits material sampling
+    fn two_light_world(light_sampling: LightSampling) -> World {
+        let mut world = HitList::new();
+        world.add(AxisRectangle::new(
+            "Y",
+            (-10.0, 10.0),
+            (0.0, 0.0),
+            (-10.0, 10.0),
+            Lambertian::from_rgb(0.5, 0.5, 0.5),
+        ));
+        world.add(Sphere::new(
+            Vec3(2.0, 0.3, 0.0),
+            0.05,
+            DiffuseLight::from_texture(SolidColor::new(10000.0, 10000.0, 10000.0)),
+        ));
+        world.add(FlipFace::new(AxisRectangle::new(
+            "Y",
+            (-4.0, 4.0),
+            (0.5, 0.5),
+            (-4.0, 4.0),
+            DiffuseLight::from_texture(SolidColor::new(1.0, 1.0, 1.0)),
+        )));
+        World::new(Arc::new(world), SolidColor::new(0.0, 0.0, 0.0)).light_sampling(light_sampling)
+    }
+
+    // Mean and variance of the direct lighting seen by a few rays onto the floor
+    fn direct_light_statistics(light_sampling: LightSampling) -> (f64, f64) {
+        let world = two_light_world(light_sampling);
+        let config = Config {
+            image_width: 1,
+            image_height: 1,
+            samples_per_pixel: 1,
+            max_depth: 2,
+            roulette_depth: 2,
+            ..Config::default()
+        };
+        let samples = 20000;
+        let mut sampler = SamplerKind::Independent.build(0, samples);
+        let mut rng = SampleRng::seed_from_u64(0);
+        let mut sum = 0.0;
+        let mut sum_squared = 0.0;
+        for (pixel, x) in [-1.0, 0.0, 1.0].iter().enumerate() {
+            for sample in 0..samples {
+                sampler.start_sample(pixel as u32, 0, sample);
+                let ray = Ray::new(Vec3(*x, 0.25, -1.0), Vec3(0.0, -0.25, 1.0), 0.0);
+                let (color, _) = world.ray_color(ray, &config, &mut *sampler, &mut rng);
+                let value = (color.x() + color.y() + color.z()) / 3.0;
+                sum += value;
+                sum_squared += value * value;
+            }
+        }
+        let count = 3.0 * samples as f64;
+        let mean = sum / count;
+        (mean, sum_squared / count - mean * mean)
+    }
+
+    #[test]
+    fn multiple_importance_sampling_lowers_variance() {
+        let (material_mean, material_variance) = direct_light_statistics(LightSampling::Material);
+        let (lights_mean, lights_variance) = direct_light_statistics(LightSampling::Lights);
+        let (multiple_mean, multiple_variance) = direct_light_statistics(LightSampling::Multiple);
+
+        assert!(multiple_variance < material_variance);
+        assert!(multiple_variance < lights_variance);
 
-        // Off into infinity
-        let (u, v) = get_sphere_uv(&ray.direction);
-        self.background.value(u, v, &ray.direction)
+        // Every strategy estimates the same image, material sampling just rarely finds the small light
+        assert!((lights_mean - multiple_mean).abs() < 0.05 * multiple_mean);
+        assert!((material_mean - multiple_mean).abs() < 0.5 * multiple_mean);
     }
 }